@@ -0,0 +1,8 @@
+use std::env;
+use std::io;
+use std::process::exit;
+use docx_git_extension::utils::cli;
+
+fn main() {
+    exit(cli::execute(env::args().skip(1), &mut io::stdout(), &mut io::stderr()));
+}
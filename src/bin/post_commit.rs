@@ -1,37 +1,11 @@
-use git2::Repository;
-use docx_git_extension::post_commit::post_commit::{create_commit, get_modified_docx_files, parse_ref_from_pointer, read_pointer_file_from_commit, resolve_tree, update_ref};
+use std::env;
+use std::io;
+use std::iter;
+use std::process::exit;
+use docx_git_extension::utils::cli;
 
+/// Runs `git-docx post-commit`, for hooks installed by earlier releases.
 fn main() {
-    let repo = Repository::discover(".").expect("Not a git repository");
-
-    let (modified_files, _deleted_files) = get_modified_docx_files(&repo);
-
-    if modified_files.is_empty() {
-        eprintln!("No .docx files added or modified in last commit.");
-    }
-
-    for path in modified_files {
-        eprintln!("📄 Processing {}...", path);
-        let pointer = match read_pointer_file_from_commit(&repo, &path) {
-            Some(p) => p,
-            None => continue,
-        };
-
-        let refname = match parse_ref_from_pointer(&pointer) {
-            Some(r) => r,
-            None => {
-                eprintln!("⚠️ No DOCX-POINTER ref found in {}", path);
-                continue;
-            }
-        };
-
-        match resolve_tree(&repo, &refname) {
-            Ok(tree) => {
-                if let Some(commit_oid) = create_commit(&repo, &path, &tree) {
-                    update_ref(&repo, &refname, commit_oid);
-                }
-            },
-            Err(e) => eprintln!("❌ Error resolving tree for {}: {}", refname, e),
-        }
-    }
-}
\ No newline at end of file
+    let args = iter::once("post-commit".to_owned()).chain(env::args().skip(1));
+    exit(cli::execute(args, &mut io::stdout(), &mut io::stderr()));
+}
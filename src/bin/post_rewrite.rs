@@ -0,0 +1,11 @@
+use std::env;
+use std::io;
+use std::iter;
+use std::process::exit;
+use docx_git_extension::utils::cli;
+
+/// Runs `git-docx post-rewrite`, for hooks installed by earlier releases.
+fn main() {
+    let args = iter::once("post-rewrite".to_owned()).chain(env::args().skip(1));
+    exit(cli::execute(args, &mut io::stdout(), &mut io::stderr()));
+}
@@ -1,229 +1,162 @@
 //! Clean filter module contains logic for unzipping the docx,
 //! saving it to a git tree and creating a pointer file that
 //! contains all the necessary metadata for docx reconstruction.
-use git2::{Repository, Oid, TreeBuilder, FileMode, Error};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as StdError;
+use std::io::Cursor;
+use git2::{Repository, Oid, FileMode, Error};
 use zip::read::ZipArchive;
-use zip::result::ZipResult;
-use std::fs::{self, File};
-use std::path::{Path, PathBuf};
-use tempfile::tempdir;
-use std::os::unix::fs::PermissionsExt;
-use tempfile::NamedTempFile;
-use zip::{write::FileOptions, ZipWriter};
-use std::convert::TryInto;
-use crate::filters::FileInfo;
-use crate::utils::utils::calculate_sha256;
-
-/// Calculates deterministic hash of the docx file and writes it to pointer file.
+use log::{debug, trace};
+use crate::filters::guard::{check_names, ArchiveLimits, Budget};
+use crate::filters::layout::{read_archive_info, ArchiveInfo};
+use crate::filters::normalize::XmlFormat;
+use crate::filters::volatile::{strip_parts, RSIDS_PART};
+use crate::filters::smudge::rezip_preserving_metadata;
+use crate::utils::utils::sha256_of_bytes;
+
+/// Calculates deterministic hash of the docx file that is stored in the pointer file.
+///
+/// # Errors
+///
+/// Returns an error if the docx cannot be rezipped.
 pub fn write_deterministic_hash(
-    src_folder: &Path,
-    file_info_list: &mut [FileInfo],
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Starting calculate_deterministic_hash");
-
-    // Sort by filename for deterministic ordering
-    file_info_list.sort_by_key(|f| f.filename.clone());
-
-    let tmp_docx = NamedTempFile::new()?.keep()?;
-    let output_docx_path = PathBuf::from(&tmp_docx.1);
-
-    let tmp_file = File::create(&output_docx_path)?;
-    let mut zip_writer = ZipWriter::new(tmp_file);
-
-    for file_info in file_info_list.iter() {
-        let file_path = src_folder.join(&file_info.filename);
-        if !file_path.is_file() {
-            println!("Missing file for ZIP: {}", file_path.display());
-            continue;
-        }
+    parts: &BTreeMap<String, Vec<u8>>,
+    archive_info: &ArchiveInfo,
+) -> Result<String, Box<dyn StdError>> {
+    debug!("Calculating deterministic hash");
 
-        let options = FileOptions::default()
-            .last_modified_time(
-                zip::DateTime::from_date_and_time(
-                    file_info.datetime.0.try_into().unwrap(),
-                    file_info.datetime.1.try_into().unwrap(),
-                    file_info.datetime.2.try_into().unwrap(),
-                    file_info.datetime.3.try_into().unwrap(),
-                    file_info.datetime.4.try_into().unwrap(),
-                    file_info.datetime.5.try_into().unwrap(),
-                )
-                .map_err(|_| "Invalid date/time")?,
-            )
-            .unix_permissions(file_info.unix_permissions);
+    let docx = rezip_preserving_metadata(parts, &archive_info.files, &archive_info.comment)?;
 
-        zip_writer.start_file(&file_info.filename, options)?;
+    let docx_hash = sha256_of_bytes(&docx);
+    debug!("Calculated SHA256 hash: {docx_hash}");
 
-        let mut f = File::open(&file_path)?;
-        std::io::copy(&mut f, &mut zip_writer)?;
-
-        println!("Added file to ZIP: {}", &file_info.filename);
-    }
-
-    zip_writer.finish()?;
-
-    let docx_hash = calculate_sha256(&output_docx_path)?;
-    println!("Calculated SHA256 hash: {}", docx_hash);
-    println!("HASH:{}", docx_hash);
+    Ok(docx_hash)
+}
 
-    Ok(())
+/// A docx stored in the repository by [`save_docx_as_git_tree`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredDocx {
+    /// Git tree holding the parts of the docx.
+    pub tree: Oid,
+    /// Deterministic hash of the docx.
+    pub hash: String,
+    /// Parts stored pretty-printed.
+    pub pretty: BTreeSet<String>,
+    /// Blob of the stripped revision identifiers, also stored in the tree as [`RSIDS_PART`].
+    pub rsids: Option<Oid>,
 }
 
-/// Unzips docx file and stores its xml components in a git tree.
-/// Retruns git tree oid.
+/// Unzips docx file and stores its xml components in a git tree, formatted as `format`
+/// selects and without their revision identifiers if `strip_rsids` is set.
+///
+/// Everything happens in memory, without temporary files.
+///
+/// # Errors
+///
+/// Returns an error if the docx cannot be unzipped within `limits` or the tree cannot be written.
 pub fn save_docx_as_git_tree(
     repo: &Repository,
     docx_bytes: &[u8],
-    mut file_info_list: &mut [FileInfo],
-) -> Result<Oid, Box<dyn std::error::Error>> {
-    // Create temporary directory
-    let tmp_dir = tempdir()?;
-    let tmp_path = tmp_dir.path();
-    let perm = fs::Permissions::from_mode(0o755);
-    fs::set_permissions(tmp_path, perm)?;
-    let docx_path = tmp_path.join("file.docx");
-
-    // Write the .docx bytes to file
-    fs::write(&docx_path, docx_bytes)?;
-
-    // Unzip .docx file
-    let file = File::open(&docx_path)?;
-    let mut zip = ZipArchive::new(file)?;
-    let unzip_path = tmp_path.join("unzipped");
-    fs::create_dir(&unzip_path)?;
-
-    #[cfg(unix)]
-    {
-        use std::os::unix::fs::PermissionsExt;
-        fs::set_permissions(&unzip_path, fs::Permissions::from_mode(0o755))?;
+    archive_info: &ArchiveInfo,
+    limits: &ArchiveLimits,
+    format: XmlFormat,
+    strip_rsids: bool,
+) -> Result<StoredDocx, Box<dyn StdError>> {
+    let mut parts = unzip(docx_bytes, limits)?;
+    let hash = write_deterministic_hash(&parts, archive_info)?;
+    // Identifiers are stripped first, so that pretty-printing never moves them.
+    let side = if strip_rsids { strip_parts(&mut parts) } else { None };
+    let pretty = format.apply(&mut parts);
+    let rsids = side.as_deref().map(|contents| repo.blob(contents)).transpose()?;
+    if let Some(contents) = side {
+        parts.insert(RSIDS_PART.to_owned(), contents);
     }
+    let tree = build_tree(repo, &parts)?;
+    Ok(StoredDocx { tree, hash, pretty, rsids })
+}
 
-    for i in 0..zip.len() {
-        let mut file = zip.by_index(i)?;
-        let out_path = unzip_path.join(file.name());
-
-        if file.name().ends_with('/') {
-            fs::create_dir_all(&out_path)?;
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                fs::set_permissions(&out_path, fs::Permissions::from_mode(0o755))?;
-            }
-        } else {
-            if let Some(parent) = out_path.parent() {
-                fs::create_dir_all(parent)?;
-                #[cfg(unix)]
-                {
-                    use std::os::unix::fs::PermissionsExt;
-                    fs::set_permissions(parent, fs::Permissions::from_mode(0o755))?;
-                }
-            }
-            let mut out_file = File::create(&out_path)?;
-            std::io::copy(&mut file, &mut out_file)?;
-
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                // If unix_mode info is available, prefer that; otherwise 0644
-                if let Some(mode) = file.unix_mode() {
-                    fs::set_permissions(&out_path, fs::Permissions::from_mode(mode))?;
-                } else {
-                    fs::set_permissions(&out_path, fs::Permissions::from_mode(0o644))?;
-                }
-            }
-        }
+/// Reads the contents of every entry of a docx by name. Directory entries are empty.
+///
+/// Entry names are checked before anything is inflated, and entries are inflated
+/// no further than `limits` allow.
+///
+/// # Errors
+///
+/// Returns an error if the docx is not a valid ZIP archive, an entry name is unsafe,
+/// an entry cannot be decompressed or the archive breaks `limits`.
+pub fn unzip(docx_bytes: &[u8], limits: &ArchiveLimits) -> Result<BTreeMap<String, Vec<u8>>, Box<dyn StdError>> {
+    let mut zip = ZipArchive::new(Cursor::new(docx_bytes))?;
+    limits.check_entries(zip.len())?;
+    let mut names = Vec::with_capacity(zip.len());
+    for index in 0..zip.len() {
+        names.push(zip.by_index_raw(index)?.name().to_owned());
     }
-
-    // Build the tree
-    let mut builder = repo.treebuilder(None)?;
-    add_directory_to_tree(repo, &unzip_path, &mut builder)?;
-
-    // Call deterministic hash function
-    write_deterministic_hash(&unzip_path, &mut file_info_list)?;
-
-    // Write tree and return OID
-    let tree_oid = builder.write()?;
-    Ok(tree_oid)
+    check_names(names.iter().map(String::as_str))?;
+
+    let mut budget = Budget::new(*limits);
+    let mut parts = BTreeMap::new();
+    for (index, name) in names.into_iter().enumerate() {
+        let zip_file = zip.by_index(index)?;
+        let compressed_size = zip_file.compressed_size();
+        let contents = budget.read(&name, zip_file, compressed_size)?;
+        trace!("Unzipped {name} ({} bytes)", contents.len());
+        parts.insert(name, contents);
+    }
+    Ok(parts)
 }
 
-/// Creates a git tree from a directory.
-pub fn add_directory_to_tree(
-    repo: &Repository,
-    base_path: &Path,
-    builder: &mut TreeBuilder,
-) -> Result<(), Error> {
-    println!("Adding directory to tree: {}", base_path.display());
-    let perm = fs::Permissions::from_mode(0o755);
-    fs::set_permissions(base_path, perm).map_err(|e| git2::Error::from_str(&format!("set_permissions failed: {}", e)))?;
-    let mut entries = fs::read_dir(base_path)
-        .map_err(|e| Error::from_str(&format!("read_dir failed: {}", e)))?
-        .filter_map(Result::ok)
-        .collect::<Vec<_>>();
-    entries.sort_by_key(|e| e.path());
+/// Writes the parts of a docx to a git tree with a subtree per directory, and
+/// returns the oid of the tree.
+///
+/// # Errors
+///
+/// Returns an error if a blob or tree cannot be written.
+pub fn build_tree(repo: &Repository, parts: &BTreeMap<String, Vec<u8>>) -> Result<Oid, Error> {
+    let entries: Vec<(&str, &[u8])> = parts.iter().map(|part| (part.0.as_str(), part.1.as_slice())).collect();
+    write_tree(repo, &entries)
+}
 
+/// Writes entries named by their path relative to the tree, recursing into directories.
+fn write_tree(repo: &Repository, entries: &[(&str, &[u8])]) -> Result<Oid, Error> {
+    let mut builder = repo.treebuilder(None)?;
+    let mut directories = BTreeMap::<&str, Vec<(&str, &[u8])>>::new();
     for entry in entries {
-        let path = entry.path();
-        let name = entry.file_name();
-
-        if path.is_file() {
-            let content = fs::read(&path)
-                .map_err(|e| Error::from_str(&format!("read file {} failed: {}", path.display(), e)))?;
-            let oid = repo.blob(&content)?;
-            builder.insert(name, oid, FileMode::Blob.into())?;
-            println!("Added file to tree: {}", path.display());
-        } else if path.is_dir() {
-            let mut sub_builder = repo.treebuilder(None)?;
-            add_directory_to_tree(repo, &path, &mut sub_builder)?;
-            let subtree_oid = sub_builder.write()?;
-            builder.insert(name, subtree_oid, FileMode::Tree.into())?;
-            println!("Added directory to tree: {}", path.display());
+        if let Some((directory, rest)) = entry.0.split_once('/') {
+            // Directory entries have nothing after the slash, but still get a subtree.
+            let children = directories.entry(directory).or_default();
+            if !rest.is_empty() {
+                children.push((rest, entry.1));
+            }
+        } else {
+            let oid = repo.blob(entry.1)?;
+            builder.insert(entry.0, oid, FileMode::Blob.into())?;
+            trace!("Added file to tree: {}", entry.0);
         }
     }
-
-    Ok(())
+    for (directory, children) in directories {
+        let subtree_oid = write_tree(repo, &children)?;
+        builder.insert(directory, subtree_oid, FileMode::Tree.into())?;
+        trace!("Added directory to tree: {directory}");
+    }
+    builder.write()
 }
 
-/// Extracts metadata for all xml files that are part of a docx file.
-/// Returns a list of FileInfo instances.
-pub fn get_file_info_from_docx<P: AsRef<Path>>(docx_path: P) -> ZipResult<Vec<FileInfo>> {
-    println!("Getting file info from docx: {}", docx_path.as_ref().display());
-
-    let file = File::open(docx_path)?;
-    let mut archive = ZipArchive::new(file)?;
-
-    let mut file_infos = Vec::new();
-
-    for i in 0..archive.len() {
-        let file = archive.by_index(i)?;
-        let name = file.name().to_string();
-        let unix_permissions = file.unix_mode().unwrap_or(0) & 0o777;
-
-        let dt = file
-            .last_modified()
-            .to_time()
-            .expect("Timestamp should always be valid");
-
-        let datetime = (
-            dt.year() as u16,
-            dt.month() as u16,
-            dt.day() as u16,
-            dt.hour() as u16,
-            dt.minute() as u16,
-            dt.second() as u16,
-        );
-
-        file_infos.push(FileInfo {
-            filename: name,
-            datetime,
-            unix_permissions,
-        });
-
-        println!(
-            "FileInfo: {} datetime={:?} perms={:o}",
-            file_infos.last().unwrap().filename,
-            datetime,
-            unix_permissions
-        );
+/// Extracts metadata for all xml files that are part of a docx file,
+/// including the raw ZIP headers and the archive comment.
+///
+/// The metadata is read from the bytes git passes to the filter, which may differ
+/// from the file in the working tree (`git add -p`, `git stash`, `git hash-object --stdin`).
+///
+/// # Errors
+///
+/// Returns an error if the docx is not a valid ZIP archive, has unsafe entry names or breaks `limits`.
+pub fn get_file_info_from_docx(docx_bytes: &[u8], limits: &ArchiveLimits) -> Result<ArchiveInfo, Box<dyn StdError>> {
+    debug!("Getting file info from {} docx bytes", docx_bytes.len());
+
+    let archive_info = read_archive_info(docx_bytes, limits)?;
+    for info in &archive_info.files {
+        trace!("FileInfo: {} datetime={:?} perms={:o}", info.filename, info.datetime, info.unix_permissions);
     }
 
-    Ok(file_infos)
-}
\ No newline at end of file
+    Ok(archive_info)
+}
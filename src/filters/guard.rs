@@ -0,0 +1,203 @@
+//! Guard module rejects archives that would escape the docx tree or exhaust memory.
+//!
+//! Entry names become paths in the tree a docx is stored as, so names that are
+//! absolute, climb out with `..` or collide with each other are refused. Entries
+//! are inflated through bounded readers, so the sizes declared by a crafted
+//! archive are never trusted.
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt::Display;
+use std::io::Read;
+use git2::{Config, ErrorCode, Repository};
+use log::warn;
+
+/// Git config key limiting the number of entries of a docx.
+pub const MAX_ENTRIES_CONFIG: &str = "docx.maxEntries";
+
+/// Git config key limiting the total size of the inflated entries of a docx.
+pub const MAX_SIZE_CONFIG: &str = "docx.maxUncompressedSize";
+
+/// Git config key limiting how many times an entry may expand when inflated.
+pub const MAX_RATIO_CONFIG: &str = "docx.maxCompressionRatio";
+
+/// Entries inflating to at most this many bytes are not held to the compression ratio,
+/// as small and repetitive XML parts legitimately compress very well.
+const RATIO_EXEMPT_SIZE: u64 = 1 << 20;
+
+/// Limits a docx must respect to be cleaned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArchiveLimits {
+    /// Maximum number of entries.
+    pub max_entries: usize,
+    /// Maximum total size of the inflated entries, in bytes.
+    pub max_uncompressed_size: u64,
+    /// Maximum ratio of the inflated to the compressed size of an entry.
+    pub max_compression_ratio: u64,
+}
+
+impl Default for ArchiveLimits {
+    fn default() -> Self {
+        Self {
+            max_entries: 10_000,
+            max_uncompressed_size: 512 << 20,
+            max_compression_ratio: 200,
+        }
+    }
+}
+
+impl ArchiveLimits {
+    /// Reads the limits from git config, falling back to the defaults for unset or invalid values.
+    #[must_use]
+    pub fn from_config(repo: &Repository) -> Self {
+        let defaults = Self::default();
+        let Ok(config) = repo.config() else {
+            return defaults;
+        };
+        Self {
+            max_entries: config_limit(&config, MAX_ENTRIES_CONFIG, defaults.max_entries),
+            max_uncompressed_size: config_limit(&config, MAX_SIZE_CONFIG, defaults.max_uncompressed_size),
+            max_compression_ratio: config_limit(&config, MAX_RATIO_CONFIG, defaults.max_compression_ratio),
+        }
+    }
+
+    /// Checks the number of entries of an archive.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are more than [`max_entries`](Self::max_entries).
+    pub fn check_entries(&self, count: usize) -> Result<(), String> {
+        if count > self.max_entries {
+            return Err(format!("ZIP archive has {count} entries, more than {MAX_ENTRIES_CONFIG} = {}", self.max_entries));
+        }
+        Ok(())
+    }
+}
+
+/// Running total of the bytes inflated from one archive.
+#[derive(Debug)]
+pub struct Budget {
+    /// Limits the archive is held to.
+    limits: ArchiveLimits,
+    /// Bytes inflated so far.
+    used: u64,
+}
+
+impl Budget {
+    /// Starts counting the entries of an archive against `limits`.
+    #[must_use]
+    pub const fn new(limits: ArchiveLimits) -> Self {
+        Self { limits, used: 0 }
+    }
+
+    /// Reads the inflated contents of the entry `name` from `reader`, stopping as
+    /// soon as they exceed what the limits allow.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry cannot be read or breaks a limit.
+    pub fn read<R: Read>(&mut self, name: &str, reader: R, compressed_size: u64) -> Result<Vec<u8>, Box<dyn Error>> {
+        let allowed = self.remaining().min(self.ratio_limit(compressed_size));
+        let mut contents = Vec::new();
+        reader.take(allowed.saturating_add(1)).read_to_end(&mut contents)?;
+        self.count(name, u64::try_from(contents.len())?, compressed_size)?;
+        Ok(contents)
+    }
+
+    /// Counts an entry of `size` inflated bytes without reading it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the entry breaks a limit.
+    pub fn count(&mut self, name: &str, size: u64, compressed_size: u64) -> Result<(), String> {
+        if size > self.ratio_limit(compressed_size) {
+            return Err(format!(
+                "ZIP entry {name:?} expands more than {MAX_RATIO_CONFIG} = {} times its compressed size",
+                self.limits.max_compression_ratio
+            ));
+        }
+        if size > self.remaining() {
+            return Err(format!(
+                "ZIP archive expands to more than {MAX_SIZE_CONFIG} = {} bytes at entry {name:?}",
+                self.limits.max_uncompressed_size
+            ));
+        }
+        self.used = self.used.saturating_add(size);
+        Ok(())
+    }
+
+    /// Bytes that may still be inflated.
+    const fn remaining(&self) -> u64 {
+        self.limits.max_uncompressed_size.saturating_sub(self.used)
+    }
+
+    /// Largest size an entry of `compressed_size` bytes may inflate to.
+    fn ratio_limit(&self, compressed_size: u64) -> u64 {
+        compressed_size
+            .saturating_mul(self.limits.max_compression_ratio)
+            .max(RATIO_EXEMPT_SIZE)
+    }
+}
+
+/// Checks that entry names are safe to store as tree paths: relative, without
+/// `.`, `..`, `.git` or empty components, unique, and never both a file and a directory.
+///
+/// # Errors
+///
+/// Returns an error describing the first offending name.
+pub fn check_names<'name, I: IntoIterator<Item = &'name str>>(names: I) -> Result<(), String> {
+    let mut seen = BTreeSet::new();
+    let mut files = BTreeSet::new();
+    let mut directories = BTreeSet::new();
+    for name in names {
+        check_name(name)?;
+        if !seen.insert(name) {
+            return Err(format!("ZIP entry name {name:?} appears more than once"));
+        }
+        if let Some(directory) = name.strip_suffix('/') {
+            directories.insert(directory);
+        } else {
+            files.insert(name);
+        }
+        directories.extend(name.match_indices('/').filter_map(|(index, _slash)| name.get(..index)));
+    }
+    if let Some(file) = files.intersection(&directories).next() {
+        return Err(format!("ZIP entry name {file:?} is both a file and a directory"));
+    }
+    Ok(())
+}
+
+/// Checks a single entry name.
+fn check_name(name: &str) -> Result<(), String> {
+    if name.starts_with('/') || name.as_bytes().get(1) == Some(&b':') {
+        return Err(format!("ZIP entry name {name:?} is absolute"));
+    }
+    if name.contains(['\\', '\0']) {
+        return Err(format!("ZIP entry name {name:?} contains a backslash or NUL character"));
+    }
+    let relative = name.strip_suffix('/').unwrap_or(name);
+    if let Some(component) = relative
+        .split('/')
+        .find(|component| matches!(*component, "" | "." | "..") || component.eq_ignore_ascii_case(".git"))
+    {
+        return Err(format!("ZIP entry name {name:?} has the invalid component {component:?}"));
+    }
+    Ok(())
+}
+
+/// Reads a positive limit from git config, warning about invalid values.
+fn config_limit<T: TryFrom<i64> + Display>(config: &Config, key: &str, default: T) -> T {
+    match config.get_i64(key) {
+        Ok(value) => match T::try_from(value) {
+            Ok(limit) if value > 0 => limit,
+            Ok(_) | Err(_) => {
+                warn!("Invalid {key} value `{value}`, using {default}");
+                default
+            }
+        },
+        Err(err) if err.code() == ErrorCode::NotFound => default,
+        Err(err) => {
+            warn!("Invalid {key} value: {}, using {default}", err.message());
+            default
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Inspect module checks and compares the documents stored in a repository.
+//!
+//! Both work on the docx trees recorded in the pointers, so they show what the
+//! filters store rather than what a zip tool would see.
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs;
+use std::io::Write;
+use git2::{DiffFormat, DiffOptions, Oid, Repository};
+use log::{debug, warn};
+use crate::filters::clean::{get_file_info_from_docx, save_docx_as_git_tree};
+use crate::filters::guard::ArchiveLimits;
+use crate::filters::normalize::XmlFormat;
+use crate::filters::volatile::strip_enabled;
+use crate::filters::pointer::Pointer;
+use crate::filters::refs::{head_pointers, tree_pointers};
+use crate::filters::smudge::{create_docx_from_commit, MismatchPolicy};
+
+/// Rebuilds every document in `HEAD`, or only the ones at `paths`, and returns
+/// by path why each document cannot be rebuilt byte for byte, or `None` if it can.
+///
+/// # Errors
+///
+/// Returns an error if the pointers in `HEAD` cannot be read.
+pub fn verify(repo: &Repository, paths: &[String]) -> Result<BTreeMap<String, Option<String>>, Box<dyn Error>> {
+    let mut results = BTreeMap::new();
+    for (path, pointer) in head_pointers(repo)? {
+        if !paths.is_empty() && !paths.contains(&path) {
+            continue;
+        }
+        debug!("Verifying {path}");
+        let failure = create_docx_from_commit(repo, &pointer, MismatchPolicy::Fail).err();
+        results.insert(path, failure.map(|err| err.to_string()));
+    }
+    for path in paths {
+        results
+            .entry(path.clone())
+            .or_insert_with(|| Some("No docx pointer at this path in HEAD".to_owned()));
+    }
+    Ok(results)
+}
+
+/// Writes a patch of the XML parts of the documents that differ between two revisions.
+///
+/// `from` is compared with the revision `to`, or the working tree if `to` is `None`.
+/// Only the documents at `paths` are compared if any are given. Documents in the
+/// working tree are stored as trees in the object database to be compared, as the
+/// clean filter would.
+///
+/// # Errors
+///
+/// Returns an error if a revision, pointer or document cannot be read, or the patch cannot be written.
+pub fn diff<W: Write>(repo: &Repository, from: &str, to: Option<&str>, paths: &[String], out: &mut W) -> Result<(), Box<dyn Error>> {
+    let old = document_trees(repo, from)?;
+    let new = match to {
+        Some(revision) => document_trees(repo, revision)?,
+        None => worktree_trees(repo, old.keys().chain(paths))?,
+    };
+    let documents: BTreeSet<&String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|path| paths.is_empty() || paths.contains(path))
+        .collect();
+
+    for path in documents {
+        let old_tree = old.get(path).map(|oid| repo.find_tree(*oid)).transpose()?;
+        let new_tree = new.get(path).map(|oid| repo.find_tree(*oid)).transpose()?;
+        let mut options = DiffOptions::new();
+        options.old_prefix(format!("a/{path}/")).new_prefix(format!("b/{path}/"));
+        let patch = repo.diff_tree_to_tree(old_tree.as_ref(), new_tree.as_ref(), Some(&mut options))?;
+
+        let mut failure = None;
+        let printed = patch.print(DiffFormat::Patch, |_delta, _hunk, line| {
+            let origin = line.origin();
+            let prefix = if matches!(origin, '+' | '-' | ' ') { write!(out, "{origin}") } else { Ok(()) };
+            match prefix.and_then(|()| out.write_all(line.content())) {
+                Ok(()) => true,
+                Err(err) => {
+                    failure = Some(err);
+                    false
+                }
+            }
+        });
+        if let Some(err) = failure {
+            return Err(err.into());
+        }
+        printed?;
+    }
+    Ok(())
+}
+
+/// Returns the docx tree of every document in `revision` by path.
+fn document_trees(repo: &Repository, revision: &str) -> Result<BTreeMap<String, Oid>, Box<dyn Error>> {
+    let tree = repo.revparse_single(revision)?.peel_to_tree()?;
+    let mut trees = BTreeMap::new();
+    for (path, pointer) in tree_pointers(repo, &tree)? {
+        match pointer.tree {
+            Some(oid) => {
+                trees.insert(path, oid);
+            }
+            None => warn!("Pointer of {path} in {revision} does not record its tree, skipping it"),
+        }
+    }
+    Ok(trees)
+}
+
+/// Returns the docx tree of every document at `paths` in the working tree.
+fn worktree_trees<'path, I: Iterator<Item = &'path String>>(repo: &Repository, paths: I) -> Result<BTreeMap<String, Oid>, Box<dyn Error>> {
+    let workdir = repo.workdir().ok_or("Cannot compare with the working tree of a bare repository")?;
+    let limits = ArchiveLimits::from_config(repo);
+    let mut trees = BTreeMap::new();
+    for path in paths {
+        let Ok(bytes) = fs::read(workdir.join(path)) else {
+            continue;
+        };
+        let tree = if Pointer::is_pointer(&bytes) {
+            Pointer::parse(str::from_utf8(&bytes)?)?.tree
+        } else {
+            let format = XmlFormat::from_attributes(repo, path);
+            let strip_rsids = strip_enabled(repo, path);
+            Some(save_docx_as_git_tree(repo, &bytes, &get_file_info_from_docx(&bytes, &limits)?, &limits, format, strip_rsids)?.tree)
+        };
+        if let Some(oid) = tree {
+            trees.insert(path.clone(), oid);
+        }
+    }
+    Ok(trees)
+}
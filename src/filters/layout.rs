@@ -0,0 +1,550 @@
+//! Layout module reads and writes the raw ZIP layout of a docx.
+//!
+//! The `zip` crate normalizes headers when it writes an archive, so a docx rebuilt
+//! with it never matches what Word wrote. This module records the header fields of
+//! the original archive (versions, flags, compression method and level, attributes,
+//! extra fields and comments) and writes them back byte for byte.
+//!
+//! Deflated data is reproduced by compressing it again with zlib at the level
+//! that gave the original bytes. Archives written by another deflate
+//! implementation (Word uses its own) match no zlib level: they are rewritten at
+//! [`DEFAULT_LEVEL`] and their bytes differ from the original, though the content
+//! is the same.
+#![expect(clippy::little_endian_bytes, reason = "ZIP headers are little-endian")]
+
+use std::error::Error;
+use std::io::{self, ErrorKind, Write};
+use crc32fast::Hasher;
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use log::{trace, warn};
+use crate::filters::FileInfo;
+use crate::filters::guard::{check_names, ArchiveLimits, Budget};
+
+/// Signature of a local file header.
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+
+/// Signature of a central directory file header.
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+
+/// Signature of the end of central directory record.
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x0605_4b50;
+
+/// Optional signature of a data descriptor.
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+
+/// Length of the end of central directory record without the comment.
+const END_OF_CENTRAL_DIRECTORY_LEN: usize = 22;
+
+/// Flag set when sizes and CRC follow the data in a data descriptor.
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+
+/// Compression method of entries stored without compression.
+pub const STORED: u16 = 0;
+
+/// Compression method of deflated entries.
+pub const DEFLATED: u16 = 8;
+
+/// Deflate level used when no level reproduces the original data.
+pub const DEFAULT_LEVEL: u32 = 6;
+
+/// Deflate levels tried when detecting the original level, most common first.
+const LEVEL_CANDIDATES: [u32; 10] = [6, 9, 1, 5, 2, 3, 4, 7, 8, 0];
+
+/// Progress of the search for the deflate level of an archive.
+///
+/// Archives are written with a single level, so it is searched for on the first
+/// deflated entry only and then checked once for each following entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LevelSearch {
+    /// No deflated entry was read yet.
+    Pending,
+    /// The level that reproduced the first deflated entry.
+    Found(u32),
+    /// No level reproduced the first deflated entry.
+    Unmatched,
+}
+
+/// Host system value of archives written on MS-DOS and Windows.
+const HOST_DOS: u16 = 0;
+
+/// Host system value of archives written on Unix.
+const HOST_UNIX: u16 = 3;
+
+/// Raw ZIP header fields of a single entry.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ZipAttributes {
+    /// Version made by, including the host system in the upper byte.
+    pub version_made_by: u16,
+    /// Version needed to extract.
+    pub version_needed: u16,
+    /// General purpose bit flags.
+    pub flags: u16,
+    /// Compression method, [`STORED`] or [`DEFLATED`].
+    pub compression_method: u16,
+    /// Deflate level that reproduces the original compressed data, if one was found.
+    pub compression_level: Option<u32>,
+    /// Internal file attributes.
+    pub internal_attributes: u16,
+    /// External file attributes.
+    pub external_attributes: u32,
+    /// Extra field of the local file header.
+    pub local_extra: Vec<u8>,
+    /// Extra field of the central directory header.
+    pub central_extra: Vec<u8>,
+    /// Entry comment.
+    pub comment: Vec<u8>,
+    /// Whether the data descriptor following the data starts with its optional signature.
+    pub descriptor_signature: bool,
+}
+
+/// Metadata of every entry of an archive along with the archive comment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ArchiveInfo {
+    /// Entries in central directory order.
+    pub files: Vec<FileInfo>,
+    /// Archive comment.
+    pub comment: Vec<u8>,
+}
+
+/// Fields of a central directory header needed to read its entry.
+struct CentralHeader {
+    /// Version made by, including the host system in the upper byte.
+    version_made_by: u16,
+    /// Version needed to extract.
+    version_needed: u16,
+    /// General purpose bit flags.
+    flags: u16,
+    /// Compression method.
+    compression_method: u16,
+    /// MS-DOS modification time.
+    time: u16,
+    /// MS-DOS modification date.
+    date: u16,
+    /// Size of the data in the archive.
+    compressed_size: u32,
+    /// Size of the data once decompressed.
+    uncompressed_size: u32,
+    /// Internal file attributes.
+    internal_attributes: u16,
+    /// External file attributes.
+    external_attributes: u32,
+    /// Offset of the local file header.
+    local_header_offset: u32,
+    /// Entry name.
+    filename: String,
+    /// Extra field of the central directory header.
+    central_extra: Vec<u8>,
+    /// Entry comment.
+    comment: Vec<u8>,
+}
+
+/// Little-endian reader over a byte slice.
+struct ByteReader<'bytes> {
+    /// Bytes being read.
+    bytes: &'bytes [u8],
+    /// Offset of the next byte to read.
+    position: usize,
+}
+
+impl<'bytes> ByteReader<'bytes> {
+    /// Creates a reader starting at `position`.
+    const fn at(bytes: &'bytes [u8], position: usize) -> Self {
+        Self { bytes, position }
+    }
+
+    /// Reads `len` bytes.
+    fn take(&mut self, len: usize) -> Result<&'bytes [u8], Box<dyn Error>> {
+        let end = self.position.checked_add(len).ok_or("Truncated ZIP archive")?;
+        let slice = self.bytes.get(self.position..end).ok_or("Truncated ZIP archive")?;
+        self.position = end;
+        Ok(slice)
+    }
+
+    /// Reads a little-endian `u16`.
+    fn read_u16(&mut self) -> Result<u16, Box<dyn Error>> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    /// Reads a little-endian `u32`.
+    fn read_u32(&mut self) -> Result<u32, Box<dyn Error>> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    /// Reads a `u16` length followed by that many bytes.
+    fn read_len(&mut self) -> Result<usize, Box<dyn Error>> {
+        Ok(usize::from(self.read_u16()?))
+    }
+
+    /// Reads a `u32` and checks it equals `signature`.
+    fn expect_signature(&mut self, signature: u32, what: &str) -> Result<(), Box<dyn Error>> {
+        if self.read_u32()? == signature {
+            Ok(())
+        } else {
+            Err(format!("Invalid ZIP archive: bad {what} signature").into())
+        }
+    }
+}
+
+/// Reads metadata of every entry of a ZIP archive, including the raw header fields
+/// needed to write it back byte for byte.
+///
+/// # Errors
+///
+/// Returns an error if the archive is malformed, uses ZIP64, has a non UTF-8 or
+/// unsafe entry name, or breaks `limits`.
+pub fn read_archive_info(bytes: &[u8], limits: &ArchiveLimits) -> Result<ArchiveInfo, Box<dyn Error>> {
+    let end_offset = find_end_of_central_directory(bytes).ok_or("Invalid ZIP archive: end of central directory not found")?;
+    let mut end = ByteReader::at(bytes, end_offset);
+    end.expect_signature(END_OF_CENTRAL_DIRECTORY_SIGNATURE, "end of central directory")?;
+    let _disk = end.read_u16()?;
+    let _central_directory_disk = end.read_u16()?;
+    let _entries_on_disk = end.read_u16()?;
+    let entry_count = end.read_u16()?;
+    let central_directory_size = end.read_u32()?;
+    let central_directory_offset = end.read_u32()?;
+    let comment_len = end.read_len()?;
+    let comment = end.take(comment_len)?.to_vec();
+
+    if entry_count == u16::MAX || central_directory_size == u32::MAX || central_directory_offset == u32::MAX {
+        return Err("ZIP64 archives are not supported".into());
+    }
+
+    limits.check_entries(usize::from(entry_count))?;
+    let mut central = ByteReader::at(bytes, usize::try_from(central_directory_offset)?);
+    let mut headers = Vec::with_capacity(usize::from(entry_count));
+    for _ in 0..entry_count {
+        headers.push(read_central_header(&mut central)?);
+    }
+    // Names are checked before any data is inflated.
+    check_names(headers.iter().map(|header| header.filename.as_str()))?;
+
+    let mut budget = Budget::new(*limits);
+    let mut search = LevelSearch::Pending;
+    let mut files = Vec::with_capacity(headers.len());
+    for header in headers {
+        files.push(read_entry(bytes, header, &mut budget, &mut search)?);
+    }
+
+    Ok(ArchiveInfo { files, comment })
+}
+
+/// Finds the offset of the end of central directory record, whose comment must reach the end of the archive.
+fn find_end_of_central_directory(bytes: &[u8]) -> Option<usize> {
+    let last = bytes.len().checked_sub(END_OF_CENTRAL_DIRECTORY_LEN)?;
+    let first = last.saturating_sub(usize::from(u16::MAX));
+    (first..=last).rev().find(|offset| {
+        let mut reader = ByteReader::at(bytes, *offset);
+        reader.read_u32().is_ok_and(|signature| signature == END_OF_CENTRAL_DIRECTORY_SIGNATURE)
+            && ByteReader::at(bytes, offset + END_OF_CENTRAL_DIRECTORY_LEN - 2)
+                .read_len()
+                .is_ok_and(|comment_len| offset + END_OF_CENTRAL_DIRECTORY_LEN + comment_len == bytes.len())
+    })
+}
+
+/// Reads one central directory header.
+fn read_central_header(central: &mut ByteReader<'_>) -> Result<CentralHeader, Box<dyn Error>> {
+    central.expect_signature(CENTRAL_HEADER_SIGNATURE, "central directory header")?;
+    let version_made_by = central.read_u16()?;
+    let version_needed = central.read_u16()?;
+    let flags = central.read_u16()?;
+    let compression_method = central.read_u16()?;
+    let time = central.read_u16()?;
+    let date = central.read_u16()?;
+    let _crc = central.read_u32()?;
+    let compressed_size = central.read_u32()?;
+    let uncompressed_size = central.read_u32()?;
+    let name_len = central.read_len()?;
+    let extra_len = central.read_len()?;
+    let comment_len = central.read_len()?;
+    let _disk_start = central.read_u16()?;
+    let internal_attributes = central.read_u16()?;
+    let external_attributes = central.read_u32()?;
+    let local_header_offset = central.read_u32()?;
+    let filename = String::from_utf8(central.take(name_len)?.to_vec())
+        .map_err(|err| format!("ZIP entry name is not UTF-8: {err}"))?;
+    let central_extra = central.take(extra_len)?.to_vec();
+    let comment = central.take(comment_len)?.to_vec();
+
+    Ok(CentralHeader {
+        version_made_by,
+        version_needed,
+        flags,
+        compression_method,
+        time,
+        date,
+        compressed_size,
+        uncompressed_size,
+        internal_attributes,
+        external_attributes,
+        local_header_offset,
+        filename,
+        central_extra,
+        comment,
+    })
+}
+
+/// Reads the local header a central directory header points to.
+/// Deflated data is inflated within `budget` to detect its compression level.
+fn read_entry(bytes: &[u8], header: CentralHeader, budget: &mut Budget, search: &mut LevelSearch) -> Result<FileInfo, Box<dyn Error>> {
+    let CentralHeader {
+        version_made_by,
+        version_needed,
+        flags,
+        compression_method,
+        time,
+        date,
+        compressed_size,
+        uncompressed_size,
+        internal_attributes,
+        external_attributes,
+        local_header_offset,
+        filename,
+        central_extra,
+        comment,
+    } = header;
+    let mut local = ByteReader::at(bytes, usize::try_from(local_header_offset)?);
+    local.expect_signature(LOCAL_HEADER_SIGNATURE, "local file header")?;
+    // Version, flags, method, time, date, CRC and sizes repeat the central directory.
+    local.take(22)?;
+    let local_name_len = local.read_len()?;
+    let local_extra_len = local.read_len()?;
+    local.take(local_name_len)?;
+    let local_extra = local.take(local_extra_len)?.to_vec();
+    let compressed = local.take(usize::try_from(compressed_size)?)?;
+    let descriptor_signature = flags & FLAG_DATA_DESCRIPTOR != 0
+        && local.read_u32().is_ok_and(|signature| signature == DATA_DESCRIPTOR_SIGNATURE);
+
+    let compression_level = if compression_method == DEFLATED {
+        let content = budget.read(&filename, DeflateDecoder::new(compressed), u64::from(compressed_size))?;
+        detect_compression_level(&filename, compressed, &content, search)
+    } else {
+        // Stored entries are as large as their data, other methods are bounded when unzipped.
+        budget.count(&filename, u64::from(uncompressed_size), u64::from(compressed_size))?;
+        None
+    };
+    trace!("ZIP entry {filename}: method={compression_method} level={compression_level:?} flags={flags:#06x}");
+
+    Ok(FileInfo {
+        filename,
+        datetime: datetime_from_dos(date, time),
+        unix_permissions: unix_mode(version_made_by, external_attributes) & 0o777,
+        zip: Some(ZipAttributes {
+            version_made_by,
+            version_needed,
+            flags,
+            compression_method,
+            compression_level,
+            internal_attributes,
+            external_attributes,
+            local_extra,
+            central_extra,
+            comment,
+            descriptor_signature,
+        }),
+    })
+}
+
+/// Finds the deflate level that compresses `content` to exactly `data`, trying
+/// every level on the first deflated entry of an archive and the level found there
+/// on the following ones.
+fn detect_compression_level(filename: &str, data: &[u8], content: &[u8], search: &mut LevelSearch) -> Option<u32> {
+    match *search {
+        LevelSearch::Pending => {
+            let found = LEVEL_CANDIDATES.into_iter().find(|level| compresses_to(content, *level, data));
+            *search = found.map_or(LevelSearch::Unmatched, LevelSearch::Found);
+            if found.is_none() {
+                warn!("{filename} was not deflated by zlib, the rebuilt docx will not match the original byte for byte");
+            }
+            found
+        }
+        LevelSearch::Found(level) => compresses_to(content, level, data).then_some(level),
+        LevelSearch::Unmatched => None,
+    }
+}
+
+/// Returns true if deflating `content` at `level` gives exactly `data`, stopping at
+/// the first byte that differs.
+fn compresses_to(content: &[u8], level: u32, data: &[u8]) -> bool {
+    let mut encoder = DeflateEncoder::new(Matcher { expected: data }, Compression::new(level));
+    encoder.write_all(content).is_ok() && encoder.finish().is_ok_and(|matcher| matcher.expected.is_empty())
+}
+
+/// Writer that compares what is written with the bytes it expects.
+struct Matcher<'data> {
+    /// Bytes not written yet.
+    expected: &'data [u8],
+}
+
+#[expect(clippy::missing_trait_methods, reason = "The provided methods all go through `write`")]
+impl Write for Matcher<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self.expected.strip_prefix(buf) {
+            Some(rest) => {
+                self.expected = rest;
+                Ok(buf.len())
+            }
+            None => Err(ErrorKind::InvalidData.into()),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compresses `content` with raw deflate at `level`.
+fn deflate(content: &[u8], level: u32) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::new(level));
+    encoder.write_all(content)?;
+    Ok(encoder.finish()?)
+}
+
+/// Converts MS-DOS date and time fields into (year, month, day, hour, minute, second).
+const fn datetime_from_dos(date: u16, time: u16) -> (u16, u16, u16, u16, u16, u16) {
+    (
+        1980 + (date >> 9),
+        (date >> 5) & 0x0f,
+        date & 0x1f,
+        time >> 11,
+        (time >> 5) & 0x3f,
+        (time & 0x1f) << 1,
+    )
+}
+
+/// Converts (year, month, day, hour, minute, second) into MS-DOS date and time fields.
+const fn datetime_to_dos(datetime: (u16, u16, u16, u16, u16, u16)) -> (u16, u16) {
+    let (year, month, day, hour, minute, second) = datetime;
+    (
+        (year.saturating_sub(1980) << 9) | (month << 5) | day,
+        (hour << 11) | (minute << 5) | (second >> 1),
+    )
+}
+
+/// Derives unix permissions the same way the `zip` crate does.
+const fn unix_mode(version_made_by: u16, external_attributes: u32) -> u32 {
+    match version_made_by >> 8 {
+        HOST_UNIX => external_attributes >> 16,
+        HOST_DOS => {
+            let mode = if external_attributes & 0x10 == 0 { 0o100_664 } else { 0o40_775 };
+            if external_attributes & 0x01 == 0 { mode } else { mode & 0o555 }
+        }
+        _ => 0,
+    }
+}
+
+/// Appends a little-endian `u16`.
+fn put_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Appends a little-endian `u32`.
+fn put_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Appends the length of `bytes` as a `u16`.
+fn put_len(buffer: &mut Vec<u8>, bytes: &[u8]) -> Result<(), Box<dyn Error>> {
+    put_u16(buffer, u16::try_from(bytes.len())?);
+    Ok(())
+}
+
+/// Writes entries as a ZIP archive, reproducing the recorded header fields of every entry.
+/// Entries are written in the given order, followed by the central directory and `comment`.
+///
+/// # Errors
+///
+/// Returns an error if an entry has no recorded ZIP attributes, uses an unsupported
+/// compression method or does not fit in a non-ZIP64 archive.
+pub fn write_archive(entries: &[(&FileInfo, &[u8])], comment: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut archive = Vec::new();
+    let mut central_directory = Vec::new();
+
+    for &(info, content) in entries {
+        let attributes = info
+            .zip
+            .as_ref()
+            .ok_or_else(|| format!("No ZIP attributes recorded for {}", info.filename))?;
+        let compressed = match attributes.compression_method {
+            STORED => content.to_vec(),
+            DEFLATED => deflate(content, attributes.compression_level.unwrap_or(DEFAULT_LEVEL))?,
+            other => return Err(format!("Unsupported compression method {other} for {}", info.filename).into()),
+        };
+        let mut hasher = Hasher::new();
+        hasher.update(content);
+        let crc = hasher.finalize();
+        let compressed_size = u32::try_from(compressed.len())?;
+        let uncompressed_size = u32::try_from(content.len())?;
+        let (date, time) = datetime_to_dos(info.datetime);
+        let name = info.filename.as_bytes();
+        let has_descriptor = attributes.flags & FLAG_DATA_DESCRIPTOR != 0;
+        let local_header_offset = u32::try_from(archive.len())?;
+
+        put_u32(&mut archive, LOCAL_HEADER_SIGNATURE);
+        put_u16(&mut archive, attributes.version_needed);
+        put_u16(&mut archive, attributes.flags);
+        put_u16(&mut archive, attributes.compression_method);
+        put_u16(&mut archive, time);
+        put_u16(&mut archive, date);
+        if has_descriptor {
+            // CRC and sizes are only known after the data, so they go to the descriptor.
+            archive.extend_from_slice(&[0; 12]);
+        } else {
+            put_u32(&mut archive, crc);
+            put_u32(&mut archive, compressed_size);
+            put_u32(&mut archive, uncompressed_size);
+        }
+        put_len(&mut archive, name)?;
+        put_len(&mut archive, &attributes.local_extra)?;
+        archive.extend_from_slice(name);
+        archive.extend_from_slice(&attributes.local_extra);
+        archive.extend_from_slice(&compressed);
+        if has_descriptor {
+            if attributes.descriptor_signature {
+                put_u32(&mut archive, DATA_DESCRIPTOR_SIGNATURE);
+            }
+            put_u32(&mut archive, crc);
+            put_u32(&mut archive, compressed_size);
+            put_u32(&mut archive, uncompressed_size);
+        }
+
+        put_u32(&mut central_directory, CENTRAL_HEADER_SIGNATURE);
+        put_u16(&mut central_directory, attributes.version_made_by);
+        put_u16(&mut central_directory, attributes.version_needed);
+        put_u16(&mut central_directory, attributes.flags);
+        put_u16(&mut central_directory, attributes.compression_method);
+        put_u16(&mut central_directory, time);
+        put_u16(&mut central_directory, date);
+        put_u32(&mut central_directory, crc);
+        put_u32(&mut central_directory, compressed_size);
+        put_u32(&mut central_directory, uncompressed_size);
+        put_len(&mut central_directory, name)?;
+        put_len(&mut central_directory, &attributes.central_extra)?;
+        put_len(&mut central_directory, &attributes.comment)?;
+        put_u16(&mut central_directory, 0); // disk number start
+        put_u16(&mut central_directory, attributes.internal_attributes);
+        put_u32(&mut central_directory, attributes.external_attributes);
+        put_u32(&mut central_directory, local_header_offset);
+        central_directory.extend_from_slice(name);
+        central_directory.extend_from_slice(&attributes.central_extra);
+        central_directory.extend_from_slice(&attributes.comment);
+    }
+
+    let entry_count = u16::try_from(entries.len())?;
+    let central_directory_offset = u32::try_from(archive.len())?;
+    let central_directory_size = u32::try_from(central_directory.len())?;
+    archive.append(&mut central_directory);
+
+    put_u32(&mut archive, END_OF_CENTRAL_DIRECTORY_SIGNATURE);
+    put_u16(&mut archive, 0); // number of this disk
+    put_u16(&mut archive, 0); // disk where central directory starts
+    put_u16(&mut archive, entry_count);
+    put_u16(&mut archive, entry_count);
+    put_u32(&mut archive, central_directory_size);
+    put_u32(&mut archive, central_directory_offset);
+    put_len(&mut archive, comment)?;
+    archive.extend_from_slice(comment);
+
+    Ok(archive)
+}
@@ -1,180 +1,192 @@
-//! Filters module implements clean and smudge filter.
-use std::env;
-use std::io::{self, BufRead, Read, Write};
-use std::process;
-use std::path::{Path, PathBuf};
-use std::fs;
+//! Filters module implements clean and smudge filter, both as single-file
+//! commands and as a long-running filter process.
+use std::error::Error;
+use std::io::{self, BufReader, BufWriter, Read as _, Write as _};
+use std::path::Path;
+use git2::Repository;
+use log::{debug, info, warn};
 use crate::utils::utils::repo_from_cwd;
-use crate::filters::smudge::{create_docx_from_commit, parse_zip_datetime};
+use crate::filters::pointer::Pointer;
+use crate::filters::refs::docx_refname;
+use crate::filters::smudge::{create_docx_from_commit, MismatchPolicy};
+use crate::filters::guard::ArchiveLimits;
+use crate::filters::layout::ZipAttributes;
+use crate::filters::normalize::XmlFormat;
+use crate::filters::volatile::strip_enabled;
 use crate::filters::clean::{save_docx_as_git_tree, get_file_info_from_docx};
+use crate::utils::utils::sha256_of_bytes;
 
+pub mod layout;
 pub mod clean;
+pub mod guard;
+pub mod inspect;
+pub mod normalize;
+pub mod pkt_line;
+pub mod pointer;
+pub mod process;
+pub mod refs;
+pub mod remote;
 pub mod smudge;
+pub mod volatile;
 
 /// A structure that contains metadata of xml file wihin a docx.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FileInfo {
+    /// Path of the file within the docx.
     filename: String,
+    /// Last modification time as (year, month, day, hour, minute, second).
     datetime: (u16, u16, u16, u16, u16, u16),
+    /// Unix permission bits of the file.
     unix_permissions: u32,
+    /// Raw ZIP header fields, absent for pointers written before they were recorded.
+    zip: Option<ZipAttributes>,
 }
 
 /// Clean filter entry point. Clean filter functionality is triggered during file staging -
-/// contents of staged file are passed to filter as an input stream via stdin. Original input 
-/// is transformed to a pointer file content that contains necessary metadata for smudge filter 
-/// (reference name, docx file hash and xml files metadata). Contents of the pointer file are 
-/// written to stdout and then to the staged file.
-pub fn clean_filter(docx_path_str: &str) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Starting clean_filter");
-
-    let args: Vec<String> = env::args().collect();
-    if args.len() < 2 {
-        writeln!(io::stderr(), "Usage: clean_filter <path_to_docx>")?;
-        process::exit(1);
-    }
-
-    let docx_path = Path::new(docx_path_str);
-    match docx_path.to_str() {
-        Some(path_str) => println!("{}", &format!("docx_path: {}", path_str)),
-        None => println!("docx_path contains invalid UTF-8"),
-    }
-    let base_name = docx_path.with_extension(""); // Remove extension
-    let base_name_str = base_name
-        .file_name()
-        .and_then(|s| s.to_str())
-        .ok_or_else(|| -> Box<dyn std::error::Error> { "Invalid base name".into() })?;
-
-    let mut stdin = io::stdin().lock();
+/// contents of staged file are passed to filter as an input stream via stdin.
+///
+/// Original input is transformed to a pointer file content that contains necessary metadata
+/// for smudge filter (reference name, docx file hash and xml files metadata). Contents of the
+/// pointer file are written to stdout and then to the staged file.
+///
+/// # Errors
+///
+/// Returns an error if the docx cannot be read, unzipped or stored in the repository.
+pub fn clean_filter(docx_path_str: &str) -> Result<(), Box<dyn Error>> {
     let mut docx_bytes = Vec::new();
-    stdin.read_to_end(&mut docx_bytes)?;
-
-    let refname = format!("refs/docx/{}", base_name_str);
-    println!("DOCX-POINTER:{}", refname);
-    println!("DOCX pointer: {}", refname);
+    io::stdin().lock().read_to_end(&mut docx_bytes)?;
 
     let repo = repo_from_cwd()?;
-    let mut docx_metadata = get_file_info_from_docx(&docx_path)?;
+    let pointer = clean(&repo, docx_path_str, &docx_bytes)?;
+    io::stdout().lock().write_all(&pointer)?;
 
-    let tree_oid = save_docx_as_git_tree(&repo, &docx_bytes, &mut docx_metadata)?;
+    Ok(())
+}
 
-    if let Some(repo_path) = repo.path().to_str() {
-        let tree_oid_file = PathBuf::from(repo_path).join("docx-tree-oid");
-        if let Err(e) = fs::write(&tree_oid_file, format!("{}\n", tree_oid)) {
-            println!("Warning: failed to write tree_oid_file: {}", e);
-        } else {
-            println!("Wrote tree_oid to {}", tree_oid_file.display());
-        }
+/// Stores the docx in the repository and returns the contents of its pointer file.
+/// Input that is already a pointer file is returned unchanged.
+///
+/// If the pointer staged for the path already describes the same document, it is
+/// returned as is: renaming a document does not modify the pointers of documents
+/// whose content did not change. A pointer is only stored again for an unchanged
+/// document if its attributes now select another [`XmlFormat`] or another setting
+/// of the `docx-strip-rsids` attribute, so that
+/// `git add --renormalize .` applies them to the documents already committed.
+///
+/// # Errors
+///
+/// Returns an error if the docx cannot be read, unzipped or stored in the repository,
+/// or is rejected by the [`ArchiveLimits`] configured for the repository.
+pub fn clean(repo: &Repository, docx_path_str: &str, docx_bytes: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    info!("Cleaning {docx_path_str}");
+
+    // A checkout that fell back to the pointer text leaves the pointer in the working tree.
+    if Pointer::is_pointer(docx_bytes) {
+        debug!("{docx_path_str} is already a docx pointer, passing it through unchanged");
+        return Ok(docx_bytes.to_vec());
     }
 
-    for meta in &docx_metadata {
-        let dt = &meta.datetime;
-        println!(
-            "METADATA:{}|({}, {}, {}, {}, {}, {})|{}",
-            meta.filename,
-            dt.0, dt.1, dt.2, dt.3, dt.4, dt.5,
-            meta.unix_permissions
-        );
-        println!("Output METADATA line for {}", meta.filename);
+    let refname = docx_refname(docx_path_str)?;
+    debug!("DOCX pointer: {refname}");
+
+    let limits = ArchiveLimits::from_config(repo);
+    let docx_metadata = get_file_info_from_docx(docx_bytes, &limits)?;
+
+    let stored = save_docx_as_git_tree(
+        repo,
+        docx_bytes,
+        &docx_metadata,
+        &limits,
+        XmlFormat::from_attributes(repo, docx_path_str),
+        strip_enabled(repo, docx_path_str),
+    )?;
+    if stored.hash == sha256_of_bytes(docx_bytes) {
+        debug!("{docx_path_str} will be reproduced byte for byte");
+    } else {
+        info!("{docx_path_str} will not be reproduced byte for byte, its ZIP layout is not fully preserved");
     }
 
-    Ok(())
-}
+    if let Some((staged, indexed)) = indexed_pointer(repo, docx_path_str)
+        && indexed.hash == stored.hash
+        && indexed.pretty.is_empty() == stored.pretty.is_empty()
+        && indexed.rsids.is_some() == stored.rsids.is_some()
+        && indexed.tree.is_some_and(|tree| repo.find_tree(tree).is_ok())
+    {
+        debug!("{docx_path_str} is unchanged, keeping its staged pointer");
+        return Ok(staged);
+    }
 
-/// Smudge filter entry point. Smudge filter functionality is triggered during file checkout -
-/// contents of the file are passed to filter as an input stream via stdin. Original input is
-/// a pointer file containing metadata necessary for docx reconstruction. Once the docx is 
-/// reconstructed, its contents are written to stdout and then to the file that is checked out.
-pub fn smudge_filter() -> Result<(), Box<dyn std::error::Error>> {
-    let stdin = io::stdin();
-    let all_lines: Vec<String> = stdin.lock().lines().collect::<Result<_, _>>()?;
-    let mut line_iter = all_lines.into_iter();
-
-    let pointer_line = match line_iter.next() {
-        Some(line) => line,
-        None => {
-            println!("Missing DOCX-POINTER");
-            return Ok(());
-        }
+    let pointer = Pointer {
+        refname,
+        hash: stored.hash,
+        tree: Some(stored.tree),
+        files: docx_metadata.files,
+        comment: docx_metadata.comment,
+        pretty: stored.pretty,
+        rsids: stored.rsids,
     };
+    Ok(pointer.serialize()?.into_bytes())
+}
 
-    if !pointer_line.starts_with("DOCX-POINTER:") {
-        println!("Missing DOCX-POINTER");
-        println!("{}", pointer_line);
-        for line in line_iter {
-            println!("{}", line);
-        }
-        return Ok(());
-    }
+/// Returns the contents of the pointer staged for `path` in the index, with the pointer they parse to.
+fn indexed_pointer(repo: &Repository, path: &str) -> Option<(Vec<u8>, Pointer)> {
+    // Paths outside the repository, such as absolute ones, have no index entry.
+    let relative = Some(Path::new(path)).filter(|candidate| candidate.is_relative())?;
+    let entry = repo.index().ok()?.get_path(relative, 0)?;
+    let blob = repo.find_blob(entry.id).ok()?;
+    let pointer = Pointer::parse(str::from_utf8(blob.content()).ok()?).ok()?;
+    Some((blob.content().to_vec(), pointer))
+}
 
-    let refname = pointer_line.splitn(2, ':').nth(1).unwrap().trim().to_string();
+/// Smudge filter entry point. Smudge filter functionality is triggered during file checkout -
+/// contents of the file are passed to filter as an input stream via stdin.
+///
+/// Original input is a pointer file containing metadata necessary for docx reconstruction.
+/// Once the docx is reconstructed, its contents are written to stdout and then to the file
+/// that is checked out.
+///
+/// # Errors
+///
+/// Returns an error if stdin cannot be read, stdout cannot be written or the pointer is invalid.
+pub fn smudge_filter() -> Result<(), Box<dyn Error>> {
+    let mut input = Vec::new();
+    io::stdin().lock().read_to_end(&mut input)?;
 
-    let hash_line = match line_iter.next() {
-        Some(line) => line,
-        None => {
-            println!("Missing HASH");
-            return Ok(());
-        }
-    };
+    let repo = repo_from_cwd()?;
+    let docx = smudge(&repo, &input)?;
+    io::stdout().lock().write_all(&docx)?;
 
-    if !hash_line.starts_with("HASH:") {
-        println!("Missing HASH");
-        println!("{}", hash_line);
-        for line in line_iter {
-            println!("{}", line);
-        }
-        return Ok(());
-    }
+    Ok(())
+}
 
-    let expected_hash = hash_line.splitn(2, ':').nth(1).unwrap().trim().to_string();
-
-    let mut file_info_list = Vec::new();
-    for line in line_iter {
-        let trimmed = line.trim();
-        if !trimmed.starts_with("METADATA:") {
-            println!("Unexpected input line (missing METADATA)");
-            println!("{}", hash_line);
-            println!("{}", line);
-            continue;
-        }
-
-        let metadata: Vec<&str> = trimmed.splitn(2, ':').nth(1).unwrap().split('|').collect();
-        if metadata.len() != 4 {
-            println!("Invalid METADATA format");
-            continue;
-        }
-
-        let datetime = match parse_zip_datetime(metadata[1]) {
-            Ok(dt) => dt,
-            Err(e) => {
-                println!("Invalid datetime for '{}': {}", metadata[0], e);
-                continue;
-            }
-        };
-
-        let file_info = FileInfo {
-            filename: metadata[0].to_string(),
-            datetime,
-            unix_permissions: metadata[3].parse().unwrap_or(0),
-        };
-
-        file_info_list.push(file_info);
-    }
+/// Long-running filter entry point. Serves clean and smudge requests sent by git
+/// over stdin and stdout using the filter process protocol until git closes the stream.
+///
+/// # Errors
+///
+/// Returns an error if the repository cannot be opened or the protocol is violated.
+pub fn process_filter() -> Result<(), Box<dyn Error>> {
+    let repo = repo_from_cwd()?;
+    let mut input = BufReader::new(io::stdin().lock());
+    let mut output = BufWriter::new(io::stdout().lock());
+    process::run(&repo, &mut input, &mut output)
+}
 
-    println!(
-        "Parsed {} metadata entries",
-        file_info_list.len()
-    );
-
-    match repo_from_cwd() {
-        Ok(repo) => {
-            if let Err(e) = create_docx_from_commit(&repo, &refname, &expected_hash, &file_info_list) {
-                println!("{}", &format!("Error in create_docx_from_commit: {e:?}"));
-            }
-        }
-        Err(e) => {
-            println!("{}", &format!("Error opening repo: {e:?}"));
-        }
+/// Rebuilds the docx described by a pointer file and returns its contents.
+/// Input that is not a pointer file is returned unchanged.
+///
+/// # Errors
+///
+/// Returns an error if the pointer is invalid or the docx cannot be rebuilt.
+pub fn smudge(repo: &Repository, input: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if !Pointer::is_pointer(input) {
+        warn!("Input is not a docx pointer, passing it through unchanged");
+        return Ok(input.to_vec());
     }
 
-    Ok(())
-}
\ No newline at end of file
+    let pointer = Pointer::parse(str::from_utf8(input)?)?;
+    debug!("Parsed {} metadata entries", pointer.files.len());
+
+    create_docx_from_commit(repo, &pointer, MismatchPolicy::from_config(repo))
+        .map_err(|err| format!("Failed to create docx from {}: {err}", pointer.refname).into())
+}
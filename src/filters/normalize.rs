@@ -0,0 +1,171 @@
+//! Normalize module optionally pretty-prints the XML parts of a docx before they
+//! are stored, so that `git diff` and delta compression work on them.
+//!
+//! Word writes most parts as a single line. Pretty-printing puts every tag that
+//! directly follows another one on its own line, indented by its depth. Only line
+//! breaks directly between `>` and `<` are inserted, and a part is only stored
+//! pretty-printed if removing them gives back its exact bytes. The pointer lists
+//! those parts, so the smudge filter knows which ones to compact again.
+//!
+//! The format is selected by the [`XML_FORMAT_ATTRIBUTE`] attribute in the committed
+//! `.gitattributes`, so that every clone stores a document the same way:
+//!
+//! ```text
+//! *.docx filter=docx docx-xml=pretty
+//! ```
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use git2::{AttrCheckFlags, AttrValue, Repository};
+use log::{trace, warn};
+
+/// Git attribute that selects the [`XmlFormat`] of a document.
+pub const XML_FORMAT_ATTRIBUTE: &str = "docx-xml";
+
+/// Indentation of one level of nesting.
+const INDENT: &[u8] = b"  ";
+
+/// Extensions of the parts that are pretty-printed.
+const XML_EXTENSIONS: [&str; 2] = [".xml", ".rels"];
+
+/// How the clean filter stores the XML parts of a docx.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum XmlFormat {
+    /// Store parts as they are in the docx (`raw`).
+    #[default]
+    Raw,
+    /// Store parts pretty-printed, one tag per line (`pretty`).
+    Pretty,
+}
+
+impl XmlFormat {
+    /// Reads the format of the document at `path` from its git attributes, falling
+    /// back to the default if unset or invalid.
+    #[must_use]
+    pub fn from_attributes(repo: &Repository, path: &str) -> Self {
+        let attribute = repo
+            .get_attr(Path::new(path), XML_FORMAT_ATTRIBUTE, AttrCheckFlags::default())
+            .ok()
+            .flatten();
+        match AttrValue::from_string(attribute) {
+            AttrValue::String(value) => value.parse().unwrap_or_else(|err| {
+                warn!("{err}, using `{}`", Self::default());
+                Self::default()
+            }),
+            AttrValue::True | AttrValue::False | AttrValue::Unspecified | AttrValue::Bytes(_) => Self::default(),
+        }
+    }
+
+    /// Formats the XML parts of a docx in place and returns the names of the parts
+    /// that were changed and must be compacted again to rebuild the docx.
+    #[must_use]
+    pub fn apply(self, parts: &mut BTreeMap<String, Vec<u8>>) -> BTreeSet<String> {
+        let mut formatted = BTreeSet::new();
+        if self == Self::Raw {
+            return formatted;
+        }
+        for (name, contents) in parts.iter_mut() {
+            let lowercase = name.to_ascii_lowercase();
+            if !XML_EXTENSIONS.iter().any(|extension| lowercase.ends_with(extension)) {
+                continue;
+            }
+            if let Some(pretty) = pretty_print(contents) {
+                trace!("Pretty-printed {name}");
+                *contents = pretty;
+                formatted.insert(name.clone());
+            }
+        }
+        formatted
+    }
+}
+
+impl FromStr for XmlFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "raw" => Ok(Self::Raw),
+            "pretty" => Ok(Self::Pretty),
+            _ => Err(format!("Unknown {XML_FORMAT_ATTRIBUTE} value `{value}`")),
+        }
+    }
+}
+
+impl fmt::Display for XmlFormat {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(match *self {
+            Self::Raw => "raw",
+            Self::Pretty => "pretty",
+        })
+    }
+}
+
+/// Compacts the parts listed in `formatted` in place, undoing [`XmlFormat::apply`].
+pub fn compact_parts(parts: &mut BTreeMap<String, Vec<u8>>, formatted: &BTreeSet<String>) {
+    for name in formatted {
+        match parts.get_mut(name) {
+            Some(contents) => *contents = compact(contents),
+            None => warn!("Missing pretty-printed part {name}"),
+        }
+    }
+}
+
+/// Puts every tag that directly follows another one on its own line. Returns
+/// `None` if nothing changed or [`compact`] would not give back `xml` exactly.
+#[must_use]
+pub fn pretty_print(xml: &[u8]) -> Option<Vec<u8>> {
+    let mut pretty = Vec::with_capacity(xml.len().saturating_mul(2));
+    let mut depth: usize = 0;
+    let mut rest = xml;
+    while let Some(index) = rest.windows(2).position(|pair| pair == b"><") {
+        let (segment, tail) = rest.split_at_checked(index + 1)?;
+        pretty.extend_from_slice(segment);
+        let (opened, closed) = count_tags(segment);
+        depth = depth.saturating_add(opened).saturating_sub(closed);
+        let level = if tail.starts_with(b"</") { depth.saturating_sub(1) } else { depth };
+        pretty.push(b'\n');
+        pretty.extend(INDENT.repeat(level));
+        rest = tail;
+    }
+    pretty.extend_from_slice(rest);
+
+    (pretty.len() != xml.len() && compact(&pretty) == xml).then_some(pretty)
+}
+
+/// Removes line breaks and indentation directly between `>` and `<`, undoing [`pretty_print`].
+#[must_use]
+pub fn compact(xml: &[u8]) -> Vec<u8> {
+    let mut compacted = Vec::with_capacity(xml.len());
+    let mut rest = xml;
+    while let Some((byte, tail)) = rest.split_first() {
+        compacted.push(*byte);
+        rest = tail;
+        if *byte == b'>'
+            && let Some(after_break) = tail.strip_prefix(b"\n")
+        {
+            let indented = after_break.iter().take_while(|next| **next == b' ').count();
+            if let Some(next_tag) = after_break.get(indented..).filter(|next| next.starts_with(b"<")) {
+                rest = next_tag;
+            }
+        }
+    }
+    compacted
+}
+
+/// Counts the tags of a segment of XML that open and close an element.
+/// Self-closing tags, declarations, comments and processing instructions count as neither.
+fn count_tags(segment: &[u8]) -> (usize, usize) {
+    let mut opened = 0;
+    let mut closed = 0;
+    for tag in segment.split(|byte| *byte == b'<').skip(1) {
+        let body = tag.split(|byte| *byte == b'>').next().unwrap_or_default();
+        match body.first().copied() {
+            Some(b'/') => closed += 1,
+            Some(b'?' | b'!') | None => {}
+            Some(_) if body.last() == Some(&b'/') => {}
+            Some(_) => opened += 1,
+        }
+    }
+    (opened, closed)
+}
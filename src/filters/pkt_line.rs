@@ -0,0 +1,142 @@
+//! Pkt-line module implements the framing used by git's long-running filter protocol.
+//!
+//! Every packet starts with its total length as four hex digits
+//! (header included); the special length `0000` is a flush packet that ends
+//! a list of packets.
+use std::io::{self, Read, Write};
+
+/// Largest payload that fits in a single packet.
+pub const MAX_DATA_LEN: usize = 65516;
+
+/// Length of the packet header.
+const HEADER_LEN: usize = 4;
+
+/// A single packet read from the stream.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    /// Flush packet (`0000`) that terminates a list.
+    Flush,
+    /// Packet carrying a payload.
+    Data(Vec<u8>),
+}
+
+/// Reads one packet. Returns `None` if the stream ended cleanly before the packet started.
+///
+/// # Errors
+///
+/// Returns an error if the stream ends in the middle of a packet or the header is invalid.
+pub fn read_packet<R: Read>(reader: &mut R) -> io::Result<Option<Packet>> {
+    let mut header = [0; HEADER_LEN];
+    let mut filled = 0;
+    while filled < HEADER_LEN {
+        let Some(unfilled) = header.get_mut(filled..) else {
+            break;
+        };
+        match reader.read(unfilled)? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated pkt-line header")),
+            read => filled += read,
+        }
+    }
+
+    let len = str::from_utf8(&header)
+        .ok()
+        .and_then(|hex| usize::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| invalid_data(format!("invalid pkt-line header {header:?}")))?;
+    if len == 0 {
+        return Ok(Some(Packet::Flush));
+    }
+    let data_len = len
+        .checked_sub(HEADER_LEN)
+        .filter(|data_len| (1..=MAX_DATA_LEN).contains(data_len))
+        .ok_or_else(|| invalid_data(format!("invalid pkt-line length {len}")))?;
+
+    let mut data = vec![0; data_len];
+    reader.read_exact(&mut data)?;
+    Ok(Some(Packet::Data(data)))
+}
+
+/// Reads text packets up to the next flush packet, stripping trailing newlines.
+/// Returns `None` if the stream ended cleanly before the list started.
+///
+/// # Errors
+///
+/// Returns an error if a packet cannot be read, is not UTF-8 or the stream ends mid-list.
+pub fn read_text_list<R: Read>(reader: &mut R) -> io::Result<Option<Vec<String>>> {
+    let mut lines = Vec::new();
+    loop {
+        match read_packet(reader)? {
+            None if lines.is_empty() => return Ok(None),
+            None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated pkt-line list")),
+            Some(Packet::Flush) => return Ok(Some(lines)),
+            Some(Packet::Data(data)) => {
+                let line = String::from_utf8(data).map_err(|err| invalid_data(err.to_string()))?;
+                lines.push(line.strip_suffix('\n').unwrap_or(&line).to_owned());
+            }
+        }
+    }
+}
+
+/// Reads binary packets up to the next flush packet and concatenates them.
+///
+/// # Errors
+///
+/// Returns an error if a packet cannot be read or the stream ends before the flush packet.
+pub fn read_content<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    loop {
+        match read_packet(reader)? {
+            None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated pkt-line content")),
+            Some(Packet::Flush) => return Ok(content),
+            Some(Packet::Data(data)) => content.extend_from_slice(&data),
+        }
+    }
+}
+
+/// Writes a single packet carrying `data`.
+///
+/// # Errors
+///
+/// Returns an error if `data` is empty or too large for one packet, or if writing fails.
+pub fn write_data<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    if data.is_empty() || data.len() > MAX_DATA_LEN {
+        return Err(invalid_data(format!("cannot write pkt-line of {} bytes", data.len())));
+    }
+    write!(writer, "{:04x}", data.len() + HEADER_LEN)?;
+    writer.write_all(data)
+}
+
+/// Writes a text packet, terminated by a newline.
+///
+/// # Errors
+///
+/// Returns an error if the line is too long for one packet or if writing fails.
+pub fn write_text<W: Write>(writer: &mut W, line: &str) -> io::Result<()> {
+    write_data(writer, format!("{line}\n").as_bytes())
+}
+
+/// Writes a flush packet.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_flush<W: Write>(writer: &mut W) -> io::Result<()> {
+    writer.write_all(b"0000")
+}
+
+/// Writes `content` split into as many packets as needed, followed by a flush packet.
+///
+/// # Errors
+///
+/// Returns an error if writing fails.
+pub fn write_content<W: Write>(writer: &mut W, content: &[u8]) -> io::Result<()> {
+    for chunk in content.chunks(MAX_DATA_LEN) {
+        write_data(writer, chunk)?;
+    }
+    write_flush(writer)
+}
+
+/// Builds an `InvalidData` error.
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
@@ -0,0 +1,453 @@
+//! Pointer module defines the format of the pointer file that the clean filter
+//! stores in place of the docx and that the smudge filter and post-commit hook
+//! read back.
+//!
+//! A pointer file is plain text, one `KEY:value` field per line:
+//!
+//! ```text
+//! DOCX-POINTER-VERSION:1
+//! REF:refs/docx/chapter
+//! HASH:<sha256 of the deterministically rezipped docx>
+//! METADATA:word/document.xml|(2024, 5, 1, 12, 30, 0)|420
+//! ```
+//!
+//! Version 2 appends the raw ZIP header fields of each entry to its `METADATA`
+//! line and may carry a hex-encoded `COMMENT` line with the archive comment, so
+//! the smudge filter can rebuild the docx byte for byte:
+//!
+//! ```text
+//! METADATA:word/document.xml|(2024, 5, 1, 12, 30, 0)|420|made=20 needed=20 flags=0 method=8 level=6 internal=0 external=0 lextra= cextra= comment= sig=0
+//! ```
+//!
+//! `METADATA` lines follow the entry order of the original archive and the docx
+//! is rebuilt in that order. Version 1 pointers list entries sorted by filename,
+//! which is the order they were hashed in.
+//!
+//! Version 3 adds a `TREE` line with the OID of the git tree holding the unzipped
+//! docx, so a pointer always rebuilds the exact document it was cleaned from even
+//! after its reference moved on:
+//!
+//! ```text
+//! TREE:4b825dc642cb6eb9a060e54bf8d69288fbee4904
+//! ```
+//!
+//! Version 4 adds a `PRETTY` line for each XML part stored pretty-printed by
+//! [`XmlFormat::Pretty`](crate::filters::normalize::XmlFormat::Pretty), which the
+//! smudge filter compacts again before rebuilding the docx:
+//!
+//! ```text
+//! PRETTY:word/document.xml
+//! ```
+//!
+//! Version 5 adds a `RSIDS` line with the OID of the blob, stored in the docx tree
+//! as [`RSIDS_PART`](crate::filters::volatile::RSIDS_PART), that holds the revision
+//! identifiers stripped from its XML parts:
+//!
+//! ```text
+//! RSIDS:8ab686eafeb1f44702738c8b0f24f2567c36da6d
+//! ```
+//!
+//! Releases before the version header was introduced wrote a `DOCX-POINTER:<ref>`
+//! line in place of both the header and `REF`, and printed their log messages into
+//! the pointer along with the fields:
+//!
+//! ```text
+//! Starting clean_filter
+//! docx_path: t2/chapter.docx
+//! DOCX-POINTER:refs/docx/chapter
+//! DOCX pointer: refs/docx/chapter
+//! Calculated SHA256 hash: c285a3d2...
+//! HASH:c285a3d2...
+//! METADATA:word/document.xml|(2024, 5, 1, 12, 30, 0)|384
+//! Output METADATA line for word/document.xml
+//! ```
+//!
+//! They are read as version 0: only the `DOCX-POINTER`, `HASH` and `METADATA`
+//! lines are kept, with the same meaning as in version 1, and every other line
+//! is skipped.
+//!
+//! Pointers are written with the lowest version able to hold their contents, and
+//! the clean filter keeps a staged pointer as it is while the document it
+//! describes does not change. The version header always comes first so that
+//! pointers written by newer releases are rejected with a clear error instead of
+//! being misread.
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
+use git2::Oid;
+use crate::filters::FileInfo;
+use crate::filters::layout::ZipAttributes;
+use crate::utils::utils::{from_hex, to_hex};
+
+/// Version of the pointer format written by this release.
+pub const POINTER_VERSION: u32 = 5;
+
+/// Oldest version of the pointer format this release can read.
+pub const FIRST_POINTER_VERSION: u32 = 1;
+
+/// Prefix of the header line that starts every pointer file.
+pub const VERSION_PREFIX: &str = "DOCX-POINTER-VERSION:";
+
+/// Prefix of the line holding the reference of version 0 pointer files.
+pub const LEGACY_PREFIX: &str = "DOCX-POINTER:";
+
+/// Field holding the custom reference the docx tree is stored under.
+const REF_PREFIX: &str = "REF:";
+
+/// Field holding the hash of the deterministically rezipped docx.
+const HASH_PREFIX: &str = "HASH:";
+
+/// Field holding the OID of the git tree the docx is stored in.
+const TREE_PREFIX: &str = "TREE:";
+
+/// Field holding the metadata of a single file within the docx.
+const METADATA_PREFIX: &str = "METADATA:";
+
+/// Field holding the OID of the blob of stripped revision identifiers.
+const RSIDS_PREFIX: &str = "RSIDS:";
+
+/// Field holding the name of a part stored pretty-printed.
+const PRETTY_PREFIX: &str = "PRETTY:";
+
+/// Field holding the hex-encoded archive comment.
+const COMMENT_PREFIX: &str = "COMMENT:";
+
+/// Errors that can occur while parsing or serializing a pointer file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// Input does not start with the pointer version header.
+    MissingHeader,
+    /// Pointer was written with a format version this release does not understand.
+    UnsupportedVersion(String),
+    /// A mandatory field is absent.
+    MissingField(&'static str),
+    /// A field that may only appear once is repeated.
+    DuplicateField(&'static str),
+    /// A line does not match any known field.
+    UnexpectedLine(String),
+    /// A `METADATA` line is malformed.
+    InvalidMetadata(String),
+    /// A value cannot be written without corrupting the line-based format.
+    InvalidValue(String),
+}
+
+impl fmt::Display for FormatError {
+    #[expect(
+        clippy::pattern_type_mismatch,
+        reason = "Matching on `*self` would require `ref` patterns, which are linted as well"
+    )]
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingHeader => write!(formatter, "not a docx pointer: missing `{VERSION_PREFIX}` header"),
+            Self::UnsupportedVersion(version) => write!(
+                formatter,
+                "unsupported docx pointer version `{version}` (this release reads versions {FIRST_POINTER_VERSION} to {POINTER_VERSION}, \
+                 and pointers without a version header as version 0)"
+            ),
+            Self::MissingField(field) => write!(formatter, "docx pointer is missing `{field}` field"),
+            Self::DuplicateField(field) => write!(formatter, "docx pointer contains `{field}` field more than once"),
+            Self::UnexpectedLine(line) => write!(formatter, "unexpected line in docx pointer: `{line}`"),
+            Self::InvalidMetadata(line) => write!(formatter, "invalid METADATA line in docx pointer: `{line}`"),
+            Self::InvalidValue(value) => write!(formatter, "value cannot be stored in docx pointer: `{value}`"),
+        }
+    }
+}
+
+#[expect(
+    clippy::missing_trait_methods,
+    reason = "Default `source` and `description` are correct for an error without an underlying cause"
+)]
+impl Error for FormatError {}
+
+/// Contents of a pointer file - everything the smudge filter needs to rebuild the docx.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pointer {
+    /// Custom reference under which the docx tree is stored.
+    pub refname: String,
+    /// Hash of the deterministically rezipped docx.
+    pub hash: String,
+    /// Git tree the docx is stored in, absent in pointers written before it was recorded.
+    pub tree: Option<Oid>,
+    /// Metadata of the files within the docx.
+    pub files: Vec<FileInfo>,
+    /// Comment of the ZIP archive.
+    pub comment: Vec<u8>,
+    /// Parts stored pretty-printed, which must be compacted to rebuild the docx.
+    pub pretty: BTreeSet<String>,
+    /// Blob of the revision identifiers stripped from the parts, if any were.
+    pub rsids: Option<Oid>,
+}
+
+impl Pointer {
+    /// Returns true if the input looks like a pointer file of any version.
+    #[must_use]
+    pub fn is_pointer(input: &[u8]) -> bool {
+        input.starts_with(VERSION_PREFIX.as_bytes()) || str::from_utf8(input).is_ok_and(is_legacy)
+    }
+
+    /// Serializes the pointer into the text stored in the git index.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a value contains a line break, which would make the
+    /// pointer unparseable.
+    pub fn serialize(&self) -> Result<String, FormatError> {
+        check_value(&self.refname)?;
+        check_value(&self.hash)?;
+
+        let mut lines = vec![
+            format!("{VERSION_PREFIX}{}", self.version()),
+            format!("{REF_PREFIX}{}", self.refname),
+            format!("{HASH_PREFIX}{}", self.hash),
+        ];
+        if let Some(tree) = self.tree {
+            lines.push(format!("{TREE_PREFIX}{tree}"));
+        }
+        if let Some(rsids) = self.rsids {
+            lines.push(format!("{RSIDS_PREFIX}{rsids}"));
+        }
+        if !self.comment.is_empty() {
+            lines.push(format!("{COMMENT_PREFIX}{}", to_hex(&self.comment)));
+        }
+        for part in &self.pretty {
+            check_value(part)?;
+            lines.push(format!("{PRETTY_PREFIX}{part}"));
+        }
+        for file in &self.files {
+            check_value(&file.filename)?;
+            let (year, month, day, hour, minute, second) = file.datetime;
+            let mut line = format!(
+                "{METADATA_PREFIX}{}|({year}, {month}, {day}, {hour}, {minute}, {second})|{}",
+                file.filename, file.unix_permissions
+            );
+            if let Some(attributes) = file.zip.as_ref() {
+                line.push('|');
+                line.push_str(&serialize_attributes(attributes));
+            }
+            lines.push(line);
+        }
+        lines.push(String::new());
+        Ok(lines.join("\n"))
+    }
+
+    /// Returns the lowest format version able to hold this pointer.
+    fn version(&self) -> u32 {
+        if self.rsids.is_some() {
+            POINTER_VERSION
+        } else if !self.pretty.is_empty() {
+            4
+        } else if self.tree.is_some() {
+            3
+        } else if !self.comment.is_empty() || self.files.iter().any(|file| file.zip.is_some()) {
+            2
+        } else {
+            FIRST_POINTER_VERSION
+        }
+    }
+
+    /// Parses pointer file contents.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the header is missing, the version is not supported,
+    /// a mandatory field is missing or any line is malformed.
+    pub fn parse(input: &str) -> Result<Self, FormatError> {
+        if is_legacy(input) {
+            return parse_legacy(input);
+        }
+        let mut lines = input.lines();
+        let header = lines.next().unwrap_or_default();
+        let version = header.strip_prefix(VERSION_PREFIX).ok_or(FormatError::MissingHeader)?.trim();
+        if !version
+            .parse::<u32>()
+            .is_ok_and(|number| (FIRST_POINTER_VERSION..=POINTER_VERSION).contains(&number))
+        {
+            return Err(FormatError::UnsupportedVersion(version.to_owned()));
+        }
+
+        let mut refname = None;
+
+        let mut hash = None;
+        let mut tree = None;
+        let mut rsids = None;
+        let mut comment = None;
+        let mut files = Vec::new();
+        let mut pretty = BTreeSet::new();
+
+        for line in lines.filter(|line| !line.trim().is_empty()) {
+            if let Some(value) = line.strip_prefix(REF_PREFIX) {
+                set_once(&mut refname, value, "REF")?;
+            } else if let Some(value) = line.strip_prefix(HASH_PREFIX) {
+                set_once(&mut hash, value, "HASH")?;
+            } else if let Some(value) = line.strip_prefix(TREE_PREFIX) {
+                set_once(&mut tree, value, "TREE")?;
+            } else if let Some(value) = line.strip_prefix(RSIDS_PREFIX) {
+                set_once(&mut rsids, value, "RSIDS")?;
+            } else if let Some(value) = line.strip_prefix(COMMENT_PREFIX) {
+                set_once(&mut comment, value, "COMMENT")?;
+            } else if let Some(value) = line.strip_prefix(PRETTY_PREFIX) {
+                if !pretty.insert(value.to_owned()) {
+                    return Err(FormatError::DuplicateField("PRETTY"));
+                }
+            } else if let Some(value) = line.strip_prefix(METADATA_PREFIX) {
+                files.push(parse_metadata(value).ok_or_else(|| FormatError::InvalidMetadata(line.to_owned()))?);
+            } else {
+                return Err(FormatError::UnexpectedLine(line.to_owned()));
+            }
+        }
+
+        Ok(Self {
+            refname: refname.ok_or(FormatError::MissingField("REF"))?,
+            hash: hash.ok_or(FormatError::MissingField("HASH"))?,
+            tree: tree.map(parse_oid).transpose()?,
+            files,
+            comment: comment
+                .map(|hex| from_hex(&hex).ok_or(FormatError::InvalidValue(hex)))
+                .transpose()?
+                .unwrap_or_default(),
+            pretty,
+            rsids: rsids.map(parse_oid).transpose()?,
+        })
+    }
+}
+
+/// Returns true if `input` is a version 0 pointer: it has no version header but a
+/// `DOCX-POINTER` line, possibly after log messages.
+fn is_legacy(input: &str) -> bool {
+    !input.starts_with(VERSION_PREFIX) && input.lines().any(|line| line.starts_with(LEGACY_PREFIX))
+}
+
+/// Parses a version 0 pointer, skipping every line but its fields.
+fn parse_legacy(input: &str) -> Result<Pointer, FormatError> {
+    let mut refname = None;
+    let mut hash = None;
+    let mut files = Vec::new();
+    for line in input.lines() {
+        if let Some(value) = line.strip_prefix(LEGACY_PREFIX) {
+            set_once(&mut refname, value, "DOCX-POINTER")?;
+        } else if let Some(value) = line.strip_prefix(HASH_PREFIX) {
+            set_once(&mut hash, value, "HASH")?;
+        } else if let Some(value) = line.strip_prefix(METADATA_PREFIX) {
+            files.push(parse_metadata(value).ok_or_else(|| FormatError::InvalidMetadata(line.to_owned()))?);
+        } else {
+            // Earlier releases wrote their log messages into the pointer.
+        }
+    }
+
+    Ok(Pointer {
+        refname: refname.ok_or(FormatError::MissingField("DOCX-POINTER"))?,
+        hash: hash.ok_or(FormatError::MissingField("HASH"))?,
+        tree: None,
+        files,
+        comment: Vec::new(),
+        pretty: BTreeSet::new(),
+        rsids: None,
+    })
+}
+
+/// Stores a field value, failing if the field was already set.
+fn set_once(slot: &mut Option<String>, value: &str, field: &'static str) -> Result<(), FormatError> {
+    if slot.is_some() {
+        return Err(FormatError::DuplicateField(field));
+    }
+    *slot = Some(value.trim().to_owned());
+    Ok(())
+}
+
+/// Parses the value of a field holding an OID.
+fn parse_oid(oid: String) -> Result<Oid, FormatError> {
+    Oid::from_str(&oid).map_err(|_err| FormatError::InvalidValue(oid))
+}
+
+/// Rejects values that would break the line-based format.
+fn check_value(value: &str) -> Result<(), FormatError> {
+    if value.contains(['\n', '\r']) {
+        return Err(FormatError::InvalidValue(value.to_owned()));
+    }
+    Ok(())
+}
+
+/// Parses `filename|(year, month, day, hour, minute, second)|permissions`, optionally
+/// followed by `|attributes`. The filename is split off last as it is the only part
+/// that may contain `|`; attributes are told apart from permissions by not being a number.
+fn parse_metadata(value: &str) -> Option<FileInfo> {
+    let (rest, zip) = match value.rsplit_once('|') {
+        Some((rest, last)) if last.trim().parse::<u32>().is_err() => (rest, Some(parse_attributes(last)?)),
+        _ => (value, None),
+    };
+    let mut parts = rest.rsplitn(3, '|');
+    let unix_permissions = parts.next()?.trim().parse().ok()?;
+    let datetime = parse_zip_datetime(parts.next()?)?;
+    let filename = parts.next()?.to_owned();
+    Some(FileInfo {
+        filename,
+        datetime,
+        unix_permissions,
+        zip,
+    })
+}
+
+/// Formats ZIP header fields as space-separated `key=value` pairs, byte fields in hex.
+fn serialize_attributes(attributes: &ZipAttributes) -> String {
+    let mut fields = vec![
+        format!("made={}", attributes.version_made_by),
+        format!("needed={}", attributes.version_needed),
+        format!("flags={}", attributes.flags),
+        format!("method={}", attributes.compression_method),
+    ];
+    if let Some(level) = attributes.compression_level {
+        fields.push(format!("level={level}"));
+    }
+    fields.extend([
+        format!("internal={}", attributes.internal_attributes),
+        format!("external={}", attributes.external_attributes),
+        format!("lextra={}", to_hex(&attributes.local_extra)),
+        format!("cextra={}", to_hex(&attributes.central_extra)),
+        format!("comment={}", to_hex(&attributes.comment)),
+        format!("sig={}", u8::from(attributes.descriptor_signature)),
+    ]);
+    fields.join(" ")
+}
+
+/// Parses ZIP header fields written by [`serialize_attributes`]. Every field but
+/// `level` is mandatory and none may repeat.
+fn parse_attributes(value: &str) -> Option<ZipAttributes> {
+    let mut fields = BTreeMap::new();
+    for pair in value.split_whitespace() {
+        let (key, field) = pair.split_once('=')?;
+        if fields.insert(key, field).is_some() {
+            return None;
+        }
+    }
+    let mut take = |key: &str| fields.remove(key);
+    let attributes = ZipAttributes {
+        version_made_by: take("made")?.parse().ok()?,
+        version_needed: take("needed")?.parse().ok()?,
+        flags: take("flags")?.parse().ok()?,
+        compression_method: take("method")?.parse().ok()?,
+        compression_level: take("level").map(str::parse).transpose().ok()?,
+        internal_attributes: take("internal")?.parse().ok()?,
+        external_attributes: take("external")?.parse().ok()?,
+        local_extra: from_hex(take("lextra")?)?,
+        central_extra: from_hex(take("cextra")?)?,
+        comment: from_hex(take("comment")?)?,
+        descriptor_signature: take("sig")? == "1",
+    };
+    fields.is_empty().then_some(attributes)
+}
+
+/// Casts `(year, month, day, hour, minute, second)` string to u16 tuple.
+fn parse_zip_datetime(date_time_str: &str) -> Option<(u16, u16, u16, u16, u16, u16)> {
+    let parts = date_time_str
+        .trim()
+        .strip_prefix('(')?
+        .strip_suffix(')')?
+        .split(',')
+        .map(|part| part.trim().parse::<u16>())
+        .collect::<Result<Vec<_>, _>>()
+        .ok()?;
+    match *parts.as_slice() {
+        [year, month, day, hour, minute, second] => Some((year, month, day, hour, minute, second)),
+        _ => None,
+    }
+}
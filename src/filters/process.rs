@@ -0,0 +1,308 @@
+//! Process module implements git's long-running filter protocol (`filter.<driver>.process`).
+//!
+//! A single filter process handles every clean and smudge request of a git command,
+//! so the repository is discovered and opened only once per checkout instead of
+//! once per file.
+//!
+//! When git offers the `delay` capability, smudge requests are answered with
+//! `status=delayed` and the documents are reconstructed in parallel by background
+//! workers. Git collects them later through `list_available_blobs`. The number of
+//! workers is read from `docx.smudgeJobs` git config and defaults to the number
+//! of available CPUs.
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::{Read, Write};
+use std::num::NonZero;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+use git2::Repository;
+use log::{debug, error, info};
+use crate::filters::{clean, smudge};
+use crate::filters::pkt_line::{read_content, read_text_list, write_content, write_flush, write_text};
+
+/// Protocol version spoken by this filter.
+const PROTOCOL_VERSION: &str = "version=2";
+
+/// Capability that lets the filter postpone smudge responses.
+const DELAY_CAPABILITY: &str = "capability=delay";
+
+/// Capabilities this filter can offer to git.
+const CAPABILITIES: [&str; 3] = ["capability=clean", "capability=smudge", DELAY_CAPABILITY];
+
+/// Git config key that sets the number of background smudge workers.
+pub const SMUDGE_JOBS_CONFIG: &str = "docx.smudgeJobs";
+
+/// A delayed smudge request: pathname and pointer contents.
+type Job = (String, Vec<u8>);
+
+/// Outcome of a delayed smudge request: pathname and reconstructed docx or error message.
+type Outcome = (String, Result<Vec<u8>, String>);
+
+/// A single clean or smudge request sent by git.
+#[derive(Debug, Default)]
+struct Request {
+    /// Requested command, `clean`, `smudge` or `list_available_blobs`.
+    command: String,
+    /// Path of the file relative to the repository root.
+    pathname: String,
+    /// Whether git accepts a delayed response to this request.
+    can_delay: bool,
+}
+
+impl Request {
+    /// Builds a request from the `key=value` lines sent by git, ignoring unknown keys.
+    fn from_lines(lines: &[String]) -> Self {
+        let mut request = Self::default();
+        for (key, value) in lines.iter().filter_map(|line| line.split_once('=')) {
+            match key {
+                "command" => value.clone_into(&mut request.command),
+                "pathname" => value.clone_into(&mut request.pathname),
+                "can-delay" => request.can_delay = value == "1",
+                _ => debug!("Ignoring request key {key}={value}"),
+            }
+        }
+        request
+    }
+}
+
+/// Background workers reconstructing delayed smudge requests.
+struct DelayQueue {
+    /// Git directory each worker opens its own repository from.
+    git_dir: PathBuf,
+    /// Number of workers to spawn on the first delayed request.
+    jobs: usize,
+    /// Sending side of the job queue, dropped to stop the workers.
+    sender: Option<Sender<Job>>,
+    /// Receiving side of the job queue, shared by the workers.
+    receiver: Arc<Mutex<Receiver<Job>>>,
+    /// Sending side of the outcome channel, cloned into each worker.
+    outcome_sender: Sender<Outcome>,
+    /// Outcomes of finished requests.
+    outcomes: Receiver<Outcome>,
+    /// Requests handed to the workers that have not finished yet.
+    pending: BTreeSet<String>,
+    /// Finished requests that have not been reported to git yet.
+    ready: BTreeMap<String, Result<Vec<u8>, String>>,
+    /// Finished requests reported to git that it has not collected yet.
+    listed: BTreeMap<String, Result<Vec<u8>, String>>,
+    /// Spawned workers.
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl DelayQueue {
+    /// Creates a queue; workers are spawned when the first request is delayed.
+    fn new(git_dir: &Path, jobs: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let (outcome_sender, outcomes) = mpsc::channel();
+        Self {
+            git_dir: git_dir.to_path_buf(),
+            jobs,
+            sender: Some(sender),
+            receiver: Arc::new(Mutex::new(receiver)),
+            outcome_sender,
+            outcomes,
+            pending: BTreeSet::new(),
+            ready: BTreeMap::new(),
+            listed: BTreeMap::new(),
+            workers: Vec::new(),
+        }
+    }
+
+    /// Hands a smudge request over to the workers.
+    fn push(&mut self, pathname: String, content: Vec<u8>) -> Result<(), Box<dyn Error>> {
+        if self.workers.is_empty() {
+            debug!("Starting {} smudge workers", self.jobs);
+            for _ in 0..self.jobs {
+                let git_dir = self.git_dir.clone();
+                let receiver = Arc::clone(&self.receiver);
+                let outcome_sender = self.outcome_sender.clone();
+                self.workers.push(thread::spawn(move || work(&git_dir, &receiver, &outcome_sender)));
+            }
+        }
+        self.pending.insert(pathname.clone());
+        self.sender
+            .as_ref()
+            .ok_or("Smudge workers already stopped")?
+            .send((pathname, content))
+            .map_err(|_job| "Smudge workers stopped unexpectedly")?;
+        Ok(())
+    }
+
+    /// Removes and returns the outcome of a finished request.
+    fn take(&mut self, pathname: &str) -> Option<Result<Vec<u8>, String>> {
+        self.listed.remove(pathname).or_else(|| self.ready.remove(pathname))
+    }
+
+    /// Returns true if the request for `pathname` was delayed and has not finished yet.
+    fn is_pending(&self, pathname: &str) -> bool {
+        self.pending.contains(pathname)
+    }
+
+    /// Returns pathnames of requests that finished since the last call.
+    /// Blocks until at least one request finishes, unless none are pending.
+    fn available(&mut self) -> Vec<String> {
+        if self.ready.is_empty()
+            && !self.pending.is_empty()
+            && let Ok(outcome) = self.outcomes.recv()
+        {
+            self.finish(outcome);
+        }
+        while let Ok(outcome) = self.outcomes.try_recv() {
+            self.finish(outcome);
+        }
+        let pathnames = self.ready.keys().cloned().collect();
+        self.listed.append(&mut self.ready);
+        pathnames
+    }
+
+    /// Moves a finished request from pending to ready.
+    fn finish(&mut self, (pathname, outcome): Outcome) {
+        self.pending.remove(&pathname);
+        self.ready.insert(pathname, outcome);
+    }
+}
+
+impl Drop for DelayQueue {
+    fn drop(&mut self) {
+        self.sender = None;
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                error!("Smudge worker panicked");
+            }
+        }
+    }
+}
+
+/// Worker loop: reconstructs documents until the job queue is closed.
+fn work(git_dir: &Path, receiver: &Mutex<Receiver<Job>>, outcome_sender: &Sender<Outcome>) {
+    let repo = Repository::open(git_dir).map_err(|err| format!("Failed to open repository: {err}"));
+    loop {
+        let job = match receiver.lock() {
+            Ok(queue) => queue.recv(),
+            Err(_poisoned) => return,
+        };
+        let Ok((pathname, content)) = job else {
+            return;
+        };
+        debug!("Reconstructing delayed {pathname}");
+        let outcome = repo
+            .as_ref()
+            .map_err(Clone::clone)
+            .and_then(|opened| smudge(opened, &content).map_err(|err| err.to_string()));
+        if outcome_sender.send((pathname, outcome)).is_err() {
+            return;
+        }
+    }
+}
+
+/// Runs the handshake and then serves requests until `input` is exhausted.
+///
+/// # Errors
+///
+/// Returns an error if reading or writing fails or the protocol is violated.
+pub fn run<R: Read, W: Write>(repo: &Repository, input: &mut R, output: &mut W) -> Result<(), Box<dyn Error>> {
+    let capabilities = handshake(input, output)?;
+    let can_delay = capabilities.contains(&DELAY_CAPABILITY);
+    let mut delayed = DelayQueue::new(repo.path(), smudge_jobs(repo));
+
+    while let Some(lines) = read_text_list(input)? {
+        let request = Request::from_lines(&lines);
+        debug!("Received {} request for {}", request.command, request.pathname);
+        if request.command == "list_available_blobs" {
+            for pathname in delayed.available() {
+                write_text(output, &format!("pathname={pathname}"))?;
+            }
+            write_flush(output)?;
+            write_text(output, "status=success")?;
+            write_flush(output)?;
+            output.flush()?;
+            continue;
+        }
+
+        let content = read_content(input)?;
+        match request.command.as_str() {
+            "clean" => respond(output, &request, clean(repo, &request.pathname, &content))?,
+            "smudge" if can_delay && request.can_delay => {
+                delayed.push(request.pathname, content)?;
+                write_text(output, "status=delayed")?;
+                write_flush(output)?;
+            }
+            "smudge" => {
+                let result = match delayed.take(&request.pathname) {
+                    Some(outcome) => outcome.map_err(Into::into),
+                    // Git asks for delayed blobs again without their content.
+                    None if delayed.is_pending(&request.pathname) || (can_delay && content.is_empty()) => {
+                        Err(format!("{} is not a reconstructed delayed blob", request.pathname).into())
+                    }
+                    None => smudge(repo, &content),
+                };
+                respond(output, &request, result)?;
+            }
+            other => respond(output, &request, Err(format!("Unsupported command `{other}`").into()))?,
+        }
+        output.flush()?;
+    }
+
+    info!("Filter process finished");
+    Ok(())
+}
+
+/// Sends the response to a clean or smudge request.
+fn respond<W: Write>(output: &mut W, request: &Request, result: Result<Vec<u8>, Box<dyn Error>>) -> Result<(), Box<dyn Error>> {
+    match result {
+        Ok(filtered) => {
+            write_text(output, "status=success")?;
+            write_flush(output)?;
+            write_content(output, &filtered)?;
+            // Empty list keeps the status sent before the content.
+            write_flush(output)?;
+        }
+        Err(err) => {
+            error!("Failed to {} {}: {err}", request.command, request.pathname);
+            write_text(output, "status=error")?;
+            write_flush(output)?;
+        }
+    }
+    Ok(())
+}
+
+/// Number of background smudge workers from git config, defaulting to the number of CPUs.
+fn smudge_jobs(repo: &Repository) -> usize {
+    repo.config()
+        .and_then(|config| config.get_i64(SMUDGE_JOBS_CONFIG))
+        .ok()
+        .and_then(|jobs| usize::try_from(jobs).ok())
+        .filter(|jobs| *jobs > 0)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZero::get))
+}
+
+/// Performs the welcome and capability negotiation. Returns the negotiated capabilities.
+fn handshake<R: Read, W: Write>(input: &mut R, output: &mut W) -> Result<Vec<&'static str>, Box<dyn Error>> {
+    let welcome = read_text_list(input)?.ok_or("Filter process input closed before handshake")?;
+    if welcome.first().map(String::as_str) != Some("git-filter-client") {
+        return Err(format!("Unexpected filter protocol welcome: {welcome:?}").into());
+    }
+    if !welcome.iter().any(|line| line == PROTOCOL_VERSION) {
+        return Err(format!("Filter protocol {PROTOCOL_VERSION} not offered by git: {welcome:?}").into());
+    }
+    write_text(output, "git-filter-server")?;
+    write_text(output, PROTOCOL_VERSION)?;
+    write_flush(output)?;
+    output.flush()?;
+
+    let offered = read_text_list(input)?.ok_or("Filter process input closed during capability negotiation")?;
+    let capabilities: Vec<_> = CAPABILITIES
+        .into_iter()
+        .filter(|capability| offered.iter().any(|line| line == capability))
+        .collect();
+    for capability in &capabilities {
+        write_text(output, capability)?;
+    }
+    write_flush(output)?;
+    output.flush()?;
+
+    debug!("Filter process handshake complete, negotiated {capabilities:?}");
+    Ok(capabilities)
+}
@@ -0,0 +1,199 @@
+//! Refs module derives the custom references docx trees are stored under.
+//!
+//! References are named after the repo-relative path of the document, so that
+//! `title1/chapter.docx` and `title2/chapter.docx` no longer share
+//! `refs/docx/chapter`. The path is escaped into a single reference component,
+//! which keeps path-derived references clear of directory/file conflicts with
+//! each other and with references written by earlier releases.
+//!
+//! References fetched from a remote are kept under `refs/remotes/<remote>/docx/`
+//! rather than over the local ones, as each clone extends its own history of a
+//! document and the two are only joined when pushing.
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write as _;
+use std::path::Path;
+use git2::{Error as GitError, ObjectType, Oid, Reference, Repository, Tree, TreeWalkMode, TreeWalkResult};
+use log::{debug, info, warn};
+use crate::filters::pointer::Pointer;
+
+/// Namespace of the custom references docx trees are stored under.
+pub const DOCX_REF_NAMESPACE: &str = "refs/docx/";
+
+/// Returns the reference a document at `path`, relative to the repository root, is stored under.
+///
+/// Every byte other than ASCII letters, digits, `-`, `_` and `.` is percent-encoded,
+/// as are dots that git does not allow at that position.
+///
+/// # Errors
+///
+/// Returns an error if the escaped path still is not a valid reference name.
+pub fn docx_refname(path: &str) -> Result<String, GitError> {
+    let mut escaped = String::with_capacity(path.len());
+    let mut previous = None;
+    for byte in path.bytes() {
+        let keep = byte.is_ascii_alphanumeric()
+            || matches!(byte, b'-' | b'_')
+            || (byte == b'.' && previous.is_some_and(|prev| prev != b'.'));
+        if keep {
+            escaped.push(char::from(byte));
+        } else {
+            // Writing to a String cannot fail.
+            write!(escaped, "%{byte:02X}").unwrap_or_default();
+        }
+        previous = Some(byte);
+    }
+    for suffix in [".lock", "."] {
+        if let Some(stem) = escaped.strip_suffix(suffix) {
+            escaped = format!("{stem}%2E{}", suffix.trim_start_matches('.'));
+        }
+    }
+
+    let refname = format!("{DOCX_REF_NAMESPACE}{escaped}");
+    if Reference::is_valid_name(&refname) {
+        Ok(refname)
+    } else {
+        Err(GitError::from_str(&format!("Cannot derive a reference name for {path}")))
+    }
+}
+
+/// Returns the fetch refspec that brings the docx references of `remote` into its
+/// remote-tracking namespace, forcing updates like the refspec of its branches.
+#[must_use]
+pub fn tracking_refspec(remote: &str) -> String {
+    format!("+{DOCX_REF_NAMESPACE}*:{}*", tracking_namespace(remote))
+}
+
+/// Returns the name `refname` is fetched into from `remote`, if it is a docx reference.
+#[must_use]
+pub fn tracking_refname(remote: &str, refname: &str) -> Option<String> {
+    refname
+        .strip_prefix(DOCX_REF_NAMESPACE)
+        .map(|name| format!("{}{name}", tracking_namespace(remote)))
+}
+
+/// Returns the remote-tracking references of `refname` that exist, in the order of the remotes.
+#[must_use]
+pub fn tracking_refnames(repo: &Repository, refname: &str) -> Vec<String> {
+    let Ok(remotes) = repo.remotes() else {
+        return Vec::new();
+    };
+    remotes
+        .iter()
+        .flatten()
+        .filter_map(|remote| tracking_refname(remote, refname))
+        .filter(|tracking| repo.find_reference(tracking).is_ok())
+        .collect()
+}
+
+/// Namespace the docx references of `remote` are fetched into.
+fn tracking_namespace(remote: &str) -> String {
+    format!("refs/remotes/{remote}/docx/")
+}
+
+/// Copies the references of documents in `HEAD` that still use a legacy
+/// `refs/docx/<basename>` name to their path-derived name. Returns the number of
+/// references created.
+///
+/// Legacy references are kept, as pointers in history still name them. A legacy
+/// reference shared by several documents only holds the last one cleaned, so those
+/// documents are skipped and have to be cleaned again (`git add --renormalize`).
+///
+/// # Errors
+///
+/// Returns an error if `HEAD` cannot be read or a reference cannot be created.
+pub fn migrate(repo: &Repository) -> Result<usize, Box<dyn Error>> {
+    let pointers = head_pointers(repo)?;
+    let mut users = BTreeMap::<&str, usize>::new();
+    for pointer in pointers.values() {
+        *users.entry(pointer.refname.as_str()).or_default() += 1;
+    }
+
+    let mut migrated = 0;
+    for (path, pointer) in &pointers {
+        let legacy = pointer.refname.as_str();
+        let refname = docx_refname(path)?;
+        if legacy == refname || repo.find_reference(&refname).is_ok() {
+            debug!("{path} already uses {refname}");
+            continue;
+        }
+        let shared = users.get(legacy).copied().unwrap_or_default();
+        if shared > 1 {
+            warn!("{legacy} is shared by {shared} documents, clean {path} again to store it under {refname}");
+            continue;
+        }
+        let Some(target) = repo.find_reference(legacy).ok().and_then(|reference| reference.target()) else {
+            warn!("{legacy} of {path} does not exist, clean {path} again to store it under {refname}");
+            continue;
+        };
+        repo.reference(&refname, target, false, &format!("Migrating DOCX ref from {legacy}"))?;
+        info!("Migrated {path} from {legacy} to {refname}");
+        migrated += 1;
+    }
+
+    Ok(migrated)
+}
+
+/// Returns the parsed pointer of every docx pointer in `HEAD` by repo-relative path.
+///
+/// # Errors
+///
+/// Returns an error if `HEAD` or one of its blobs cannot be read.
+pub fn head_pointers(repo: &Repository) -> Result<BTreeMap<String, Pointer>, Box<dyn Error>> {
+    tree_pointers(repo, &repo.head()?.peel_to_tree()?)
+}
+
+/// Returns the parsed pointer of every docx pointer in `tree` by path within the tree.
+///
+/// # Errors
+///
+/// Returns an error if the tree or one of its blobs cannot be read.
+pub fn tree_pointers(repo: &Repository, tree: &Tree<'_>) -> Result<BTreeMap<String, Pointer>, Box<dyn Error>> {
+    let mut blobs = Vec::new();
+    tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+        if entry.kind() == Some(ObjectType::Blob)
+            && let Some(name) = entry.name()
+            && is_docx_path(Path::new(name))
+        {
+            blobs.push((format!("{dir}{name}"), entry.id()));
+        }
+        TreeWalkResult::Ok
+    })?;
+
+    let mut pointers = BTreeMap::new();
+    for (path, oid) in blobs {
+        if let Some(pointer) = blob_pointer(repo, &path, oid)? {
+            pointers.insert(path, pointer);
+        }
+    }
+    Ok(pointers)
+}
+
+/// Returns true if `path` names a docx file.
+#[must_use]
+pub fn is_docx_path(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case("docx"))
+}
+
+/// Parses the blob `oid` stored at `path` as a docx pointer.
+///
+/// Returns `None` for blobs that are not pointers, and warns about invalid ones.
+///
+/// # Errors
+///
+/// Returns an error if the blob cannot be read.
+pub fn blob_pointer(repo: &Repository, path: &str, oid: Oid) -> Result<Option<Pointer>, GitError> {
+    let blob = repo.find_blob(oid)?;
+    if !Pointer::is_pointer(blob.content()) {
+        return Ok(None);
+    }
+    match str::from_utf8(blob.content()).map_err(|err| err.to_string()).and_then(|text| {
+        Pointer::parse(text).map_err(|err| err.to_string())
+    }) {
+        Ok(pointer) => Ok(Some(pointer)),
+        Err(err) => {
+            warn!("Skipping invalid docx pointer {path}: {err}");
+            Ok(None)
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Remote module fetches custom references the smudge filter cannot find locally.
+//!
+//! `git clone` and `git fetch` only bring in branches and tags, so the trees of
+//! documents in a fresh clone are missing until `refs/docx/*` is fetched as well.
+//! They are fetched into the remote-tracking namespace of the remote (see
+//! [`tracking_refspec`]), which the smudge filter falls back to.
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+use git2::{Cred, CredentialType, Error as GitError, ErrorCode, FetchOptions, Oid, RemoteCallbacks, Repository};
+use log::info;
+use crate::filters::refs::{tracking_refname, tracking_refspec, DOCX_REF_NAMESPACE};
+
+/// Git config key that enables fetching missing references during smudge, on by default.
+pub const AUTO_FETCH_CONFIG: &str = "docx.autoFetch";
+
+/// Git config key naming the remote missing references are fetched from, `origin` by default.
+pub const FETCH_REMOTE_CONFIG: &str = "docx.remote";
+
+/// Remote fetched from when [`FETCH_REMOTE_CONFIG`] is unset.
+const DEFAULT_REMOTE: &str = "origin";
+
+/// Git directories of the repositories whose namespace was fetched in this process.
+static FETCHED: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
+
+/// How often the credentials callback is asked before giving up.
+const CREDENTIAL_ATTEMPTS: u32 = 3;
+
+/// Returns true unless fetching missing references is disabled in git config.
+#[must_use]
+pub fn auto_fetch(repo: &Repository) -> bool {
+    repo.config()
+        .and_then(|config| config.get_bool(AUTO_FETCH_CONFIG))
+        .unwrap_or(true)
+}
+
+/// Fetches every reference under [`DOCX_REF_NAMESPACE`] from the configured remote,
+/// until it succeeds once per process and repository.
+///
+/// Delayed smudge requests are reconstructed by several workers, so the first one
+/// missing a tree fetches while the others wait for it, and all of them then retry
+/// with what was fetched. A failed fetch is tried again by the next document missing
+/// a tree, as the remote may have been unreachable only for a moment. Local
+/// references are never overwritten, as the namespace is fetched into the
+/// remote-tracking references of the remote.
+///
+/// # Errors
+///
+/// Returns an error if the remote does not exist or cannot be fetched from.
+pub fn fetch_documents(repo: &Repository) -> Result<(), String> {
+    // The lock is held while fetching so that concurrent workers wait for the one fetch.
+    let mut fetched = FETCHED.lock().unwrap_or_else(PoisonError::into_inner);
+    if fetched.contains(repo.path()) {
+        return Ok(());
+    }
+    fetch_namespace(repo).map_err(|err| err.to_string())?;
+    fetched.insert(repo.path().to_path_buf());
+    drop(fetched);
+    Ok(())
+}
+
+/// Fetches every reference under [`DOCX_REF_NAMESPACE`] from the configured remote.
+fn fetch_namespace(repo: &Repository) -> Result<(), Box<dyn Error>> {
+    let name = repo
+        .config()
+        .and_then(|config| config.get_string(FETCH_REMOTE_CONFIG))
+        .unwrap_or_else(|_err| DEFAULT_REMOTE.to_owned());
+    let mut remote = repo
+        .find_remote(&name)
+        .map_err(|err| format!("Failed to find remote '{name}' to fetch {DOCX_REF_NAMESPACE}* from: {err}"))?;
+
+    info!("Fetching {DOCX_REF_NAMESPACE}* from {name}");
+    remote
+        .fetch(&[tracking_refspec(&name)], Some(&mut options(repo)?), None)
+        .map_err(|err| format!("Failed to fetch {DOCX_REF_NAMESPACE}* from {name}: {err}"))?;
+    Ok(())
+}
+
+/// Fetches `refnames` from `remote`, a remote name or URL, and returns the commit
+/// each of them points to on the remote, for those it has.
+///
+/// References of a named remote are fetched into its remote-tracking references.
+///
+/// # Errors
+///
+/// Returns an error if the remote cannot be fetched from.
+pub fn fetch_tips(repo: &Repository, remote: &str, refnames: &BTreeSet<String>) -> Result<BTreeMap<String, Oid>, Box<dyn Error>> {
+    let mut found = repo.find_remote(remote).or_else(|_err| repo.remote_anonymous(remote))?;
+    let refspecs: Vec<String> = refnames
+        .iter()
+        .map(|refname| {
+            found
+                .name()
+                .and_then(|name| tracking_refname(name, refname))
+                .map_or_else(|| refname.clone(), |tracking| format!("+{refname}:{tracking}"))
+        })
+        .collect();
+    let url = found.url_bytes().to_vec();
+
+    info!("Fetching {} docx references from {remote}", refspecs.len());
+    found
+        .fetch(&refspecs, Some(&mut options(repo)?), None)
+        .map_err(|err| format!("Failed to fetch docx references from {remote}: {err}"))?;
+
+    // The fetched commits are read back from FETCH_HEAD, which a fetch rewrites.
+    let mut tips = BTreeMap::new();
+    repo.fetchhead_foreach(|refname, fetched_from, oid, _is_merge| {
+        if fetched_from == url.as_slice() && refnames.contains(refname) {
+            tips.insert(refname.to_owned(), *oid);
+        }
+        true
+    })
+    .or_else(|err| if err.code() == ErrorCode::NotFound { Ok(()) } else { Err(err) })?;
+    Ok(tips)
+}
+
+/// Fetch options authenticating with the SSH agent or git's credential helpers.
+fn options(repo: &Repository) -> Result<FetchOptions<'static>, GitError> {
+    let config = repo.config()?;
+    let mut attempts = 0;
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.credentials(move |url, username, allowed| {
+        attempts += 1;
+        if attempts > CREDENTIAL_ATTEMPTS {
+            return Err(GitError::from_str(&format!("Authentication to {url} failed")));
+        }
+        if allowed.contains(CredentialType::SSH_KEY) {
+            Cred::ssh_key_from_agent(username.unwrap_or("git"))
+        } else if allowed.contains(CredentialType::USER_PASS_PLAINTEXT) {
+            Cred::credential_helper(&config, url, username)
+        } else {
+            Cred::default()
+        }
+    });
+    let mut options = FetchOptions::new();
+    options.remote_callbacks(callbacks);
+    Ok(options)
+}
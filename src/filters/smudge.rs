@@ -1,203 +1,305 @@
 //! Smudge filter module contains logic for reconstuction of
 //! the docx file according to data provided in the pointer file.
 
-use std::io::{Read, Write};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{Cursor, Write as _};
+use std::str::FromStr;
+use chrono::Local;
 use git2::{Repository, Tree, ObjectType};
-use std::fs::{self, File};
-use std::path::Path;
-use tempfile::tempdir;
-use zip::{write::FileOptions, ZipWriter};
-use std::convert::TryInto;
+use zip::{write::FileOptions, ZipWriter, DateTime};
+use log::{debug, error, trace, warn};
 use crate::filters::FileInfo;
-use crate::utils::utils::calculate_sha256;
+use crate::filters::layout::{write_archive, DEFLATED, STORED};
+use crate::filters::normalize::compact_parts;
+use crate::filters::pointer::Pointer;
+use crate::filters::refs::tracking_refnames;
+use crate::filters::remote::{auto_fetch, fetch_documents};
+use crate::filters::volatile::{restore_parts, RSIDS_PART};
+use crate::utils::utils::sha256_of_bytes;
 
-/// Reads commit oid that is written to a custom reference and finds a 
-/// git tree that is referenced by the commit oid. Reconstructs the original
-/// docx file from the git tree.
-pub fn create_docx_from_commit(
-    repo: &Repository,
-    refname: &str,
-    expected_hash: &str,
-    file_info_list: &[FileInfo],
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Creating DOCX from ref '{}'", refname);
+/// Git config key that selects the [`MismatchPolicy`].
+pub const MISMATCH_POLICY_CONFIG: &str = "docx.onHashMismatch";
 
-    let reference = repo
-        .find_reference(refname)
-        .map_err(|e| format!("Failed to find ref '{}': {}", refname, e))?;
-    let object = reference.peel(ObjectType::Any)?;
+/// File under the `.git` directory where hash mismatches are recorded.
+pub const WARNINGS_FILE: &str = "docx-warnings.log";
 
-    let tree = match object.kind() {
-        Some(ObjectType::Commit) => {
-            let commit = object
-                .into_commit()
-                .map_err(|_| "Expected commit object")?;
-            println!(
-                "Resolved {} to commit {}",
-                refname,
-                commit.id()
-            );
-            commit.tree()?
-        }
-        Some(ObjectType::Tree) => {
-            match object.into_tree() {
-                Ok(tree) => {
-                    println!("Resolved {} to tree {}", refname, tree.id());
-                    tree
-                }
-                Err(obj) => {
-                    return Err(format!("Expected Tree but got {:?}", obj.kind()).into());
-                }
-            }
-        }
-        _ => {
-            return Err(format!(
-                "Unsupported object type at {}: {:?}",
-                refname,
-                object.kind()
-            )
-            .into());
-        }
-    };
+/// What the smudge filter writes when the rebuilt docx does not match the pointer hash.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MismatchPolicy {
+    /// Fail the checkout of the document (`fail`).
+    #[default]
+    Fail,
+    /// Write the rebuilt docx even though it is not byte-identical (`reconstruction`).
+    Reconstruction,
+    /// Write the pointer file text instead of the docx (`pointer`).
+    Pointer,
+}
+
+impl MismatchPolicy {
+    /// Reads the policy from git config, falling back to the default if unset or invalid.
+    #[must_use]
+    pub fn from_config(repo: &Repository) -> Self {
+        let Ok(value) = repo.config().and_then(|config| config.get_string(MISMATCH_POLICY_CONFIG)) else {
+            return Self::default();
+        };
+        value.parse().unwrap_or_else(|err| {
+            warn!("{err}, using `{}`", Self::default());
+            Self::default()
+        })
+    }
+}
 
-    let tmpdir = tempdir()?;
-    let tmp_path = tmpdir.path();
+impl FromStr for MismatchPolicy {
+    type Err = String;
 
-    extract_tree(repo, &tree, tmp_path)?;
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "fail" => Ok(Self::Fail),
+            "reconstruction" => Ok(Self::Reconstruction),
+            "pointer" => Ok(Self::Pointer),
+            _ => Err(format!("Unknown {MISMATCH_POLICY_CONFIG} value `{value}`")),
+        }
+    }
+}
 
-    let docx_name = refname
-        .rsplit('/')
-        .next()
-        .unwrap_or("output")
-        .to_owned() + ".docx";
-    let docx_path = tmp_path.join(&docx_name);
+impl fmt::Display for MismatchPolicy {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(match *self {
+            Self::Fail => "fail",
+            Self::Reconstruction => "reconstruction",
+            Self::Pointer => "pointer",
+        })
+    }
+}
 
-    println!("docx_name {}", docx_name);
+/// Finds the git tree recorded in the pointer and reconstructs the original docx
+/// file from it, returning its contents.
+///
+/// Pointers without a tree, or whose tree is missing from the repository, fall back
+/// to the tree of the commit at their reference. If neither is found, the reference
+/// is fetched from the remote unless [`AUTO_FETCH_CONFIG`](crate::filters::remote::AUTO_FETCH_CONFIG) disables it.
+///
+/// If the rebuilt docx does not match the hash in the pointer, the mismatch is
+/// recorded in [`WARNINGS_FILE`] and `policy` decides what is returned.
+///
+/// # Errors
+///
+/// Returns an error if the tree cannot be resolved, the docx cannot be rebuilt
+/// or its hash does not match and `policy` is [`MismatchPolicy::Fail`].
+pub fn create_docx_from_commit(
+    repo: &Repository,
+    pointer: &Pointer,
+    policy: MismatchPolicy,
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    let refname = pointer.refname.as_str();
+    let expected_hash = pointer.hash.as_str();
 
-    rezip_preserving_metadata(tmp_path, file_info_list, &docx_path)?;
+    let tree = match resolve_tree(repo, pointer) {
+        Ok(tree) => tree,
+        Err(err) if auto_fetch(repo) => {
+            warn!("{err}, fetching it");
+            fetch_documents(repo)?;
+            resolve_tree(repo, pointer)?
+        }
+        Err(err) => return Err(err),
+    };
 
-    let rezipped_sha = calculate_sha256(&docx_path)?;
-    let mut buffer = Vec::new();
+    let mut parts = read_tree(repo, &tree)?;
+    compact_parts(&mut parts, &pointer.pretty);
+    if let Some(rsids) = pointer.rsids {
+        parts.remove(RSIDS_PART);
+        restore_parts(&mut parts, repo.find_blob(rsids)?.content())?;
+    }
+    let buffer = rezip_preserving_metadata(&parts, &pointer.files, &pointer.comment)?;
+    let rezipped_sha = sha256_of_bytes(&buffer);
 
     if expected_hash == rezipped_sha {
-        println!("Hash matched: {}", rezipped_sha);
-        File::open(&docx_path)?.read_to_end(&mut buffer)?;
-    } else {
-        println!(
-            "Hash mismatch. Expected: {}, Got: {}",
-            expected_hash, rezipped_sha
-        );
+        debug!("Hash matched: {rezipped_sha}");
+        return Ok(buffer);
     }
-    
-    std::io::stdout().write_all(&buffer)?;
 
-    Ok(())
+    let message = format!(
+        "Hash mismatch for {refname}. Expected: {expected_hash}, Got: {rezipped_sha}. Applying `{policy}` policy"
+    );
+    record_warning(repo, &message);
+    match policy {
+        MismatchPolicy::Fail => Err(message.into()),
+        MismatchPolicy::Reconstruction => Ok(buffer),
+        MismatchPolicy::Pointer => Ok(pointer.serialize()?.into_bytes()),
+    }
 }
 
-/// Extracts xml files from a git tree to a specified path.
-pub fn extract_tree(repo: &Repository, tree: &Tree, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    fs::create_dir_all(path)?;
+/// Finds the tree recorded in the pointer, falling back to the tree at its reference.
+fn resolve_tree<'repo>(repo: &'repo Repository, pointer: &Pointer) -> Result<Tree<'repo>, Box<dyn Error>> {
+    let refname = pointer.refname.as_str();
+    match pointer.tree.map(|oid| repo.find_tree(oid)) {
+        Some(Ok(tree)) => {
+            debug!("Creating DOCX from tree {}", tree.id());
+            Ok(tree)
+        }
+        Some(Err(err)) => {
+            warn!("Tree of {refname} not found ({err}), falling back to the ref");
+            resolve_ref_tree(repo, refname)
+        }
+        None => resolve_ref_tree(repo, refname),
+    }
+}
 
-    println!("Extracting tree to {}", path.display());
+/// Finds the tree of the commit (or the tree) a custom reference points to, falling
+/// back to its remote-tracking references if it does not exist locally.
+fn resolve_ref_tree<'repo>(repo: &'repo Repository, refname: &str) -> Result<Tree<'repo>, Box<dyn Error>> {
+    debug!("Creating DOCX from ref '{refname}'");
 
-    for entry in tree.iter() {
-        let name = entry.name().unwrap_or("<invalid>");
-        let full_path = path.join(name);
-        let obj = entry.to_object(repo)?;
+    let reference = match repo.find_reference(refname) {
+        Ok(reference) => reference,
+        Err(err) => tracking_refnames(repo, refname)
+            .iter()
+            .find_map(|tracking| repo.find_reference(tracking).ok())
+            .ok_or_else(|| format!("Failed to find ref '{refname}': {err}"))?,
+    };
+    let object = reference.peel(ObjectType::Any)?;
 
-        match obj.kind() {
-            Some(ObjectType::Tree) => {
-                let subtree = obj.as_tree().expect("Expected tree");
-                extract_tree(repo, subtree, &full_path)?;
-            }
-            Some(ObjectType::Blob) => {
-                let blob = obj.as_blob().expect("Expected blob");
-                fs::write(&full_path, blob.content())?;
-                println!("Extracted file: {}", full_path.display());
-            }
-            _ => {
-                println!("Skipping non-blob/tree object: {}", name);
-            }
+    match object.kind() {
+        Some(ObjectType::Commit) => {
+            let commit = object
+                .into_commit()
+                .map_err(|_object| "Expected commit object")?;
+            debug!("Resolved {refname} to commit {}", commit.id());
+            Ok(commit.tree()?)
+        }
+        Some(ObjectType::Tree) => {
+            let tree = object
+                .into_tree()
+                .map_err(|_object| "Expected tree object")?;
+            debug!("Resolved {refname} to tree {}", tree.id());
+            Ok(tree)
+        }
+        Some(ObjectType::Any | ObjectType::Blob | ObjectType::Tag) | None => {
+            Err(format!("Unsupported object type at {refname}: {:?}", object.kind()).into())
         }
     }
+}
 
-    Ok(())
+/// Logs a warning and appends it to [`WARNINGS_FILE`] so it outlives the checkout output.
+fn record_warning(repo: &Repository, message: &str) {
+    warn!("{message}");
+    let path = repo.path().join(WARNINGS_FILE);
+    let line = format!("{} {message}\n", Local::now().format("%Y-%m-%d %H:%M:%S"));
+    if let Err(err) = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(line.as_bytes()))
+    {
+        error!("Failed to record warning in {}: {err}", path.display());
+    }
+}
+
+/// Reads the contents of every file in a git tree by path.
+///
+/// # Errors
+///
+/// Returns an error if an object cannot be read.
+pub fn read_tree(repo: &Repository, tree: &Tree<'_>) -> Result<BTreeMap<String, Vec<u8>>, Box<dyn Error>> {
+    let mut parts = BTreeMap::new();
+    collect_tree(repo, tree, "", &mut parts)?;
+    Ok(parts)
 }
 
-/// Casts date time string to u16 tuple.
-pub fn parse_zip_datetime(date_time_str: &str) -> Result<(u16, u16, u16, u16, u16, u16), Box<dyn std::error::Error>> {
-    let parts: Vec<u16> = date_time_str
-        .trim_matches(|c| c == '(' || c == ')')
-        .split(", ")
-        .map(|s| s.trim().parse::<u16>())
-        .collect::<Result<_, _>>()?;
+/// Adds the files of a tree to `parts`, prefixing their paths with `prefix`.
+fn collect_tree(repo: &Repository, tree: &Tree<'_>, prefix: &str, parts: &mut BTreeMap<String, Vec<u8>>) -> Result<(), Box<dyn Error>> {
+    for entry in tree {
+        let name = entry.name().unwrap_or("<invalid>");
+        let path = format!("{prefix}{name}");
+        let obj = entry.to_object(repo)?;
 
-    if parts.len() != 6 {
-        return Err("Invalid datetime format, expected 6 values".into());
+        if let Some(subtree) = obj.as_tree() {
+            collect_tree(repo, subtree, &format!("{path}/"), parts)?;
+        } else if let Some(blob) = obj.as_blob() {
+            trace!("Read file: {path}");
+            parts.insert(path, blob.content().to_vec());
+        } else {
+            warn!("Skipping non-blob/tree object: {name}");
+        }
     }
 
-    Ok((
-        parts[0], // year
-        parts[1], // month
-        parts[2], // day
-        parts[3], // hour
-        parts[4], // minute
-        parts[5], // second
-    ))
+    Ok(())
 }
 
-/// Recreates docx with original metadata from pointer file.
+/// Recreates docx with original metadata from pointer file and returns its contents.
+/// Entries are written in the order they are listed in the pointer.
+///
+/// When the pointer records the raw ZIP headers of every entry, the archive is
+/// written byte for byte as it was cleaned. Pointers without them, or with an
+/// entry compressed by a method other than stored or deflated, are rebuilt with
+/// the `zip` crate.
+///
+/// # Errors
+///
+/// Returns an error if the ZIP cannot be written.
 pub fn rezip_preserving_metadata(
-    src_folder: &Path,
+    parts: &BTreeMap<String, Vec<u8>>,
     file_info_list: &[FileInfo],
-    output_docx_path: &Path,
-) -> Result<(), Box<dyn std::error::Error>> {
-    println!("Creating ZIP at {}", output_docx_path.display());
+    comment: &[u8],
+) -> Result<Vec<u8>, Box<dyn Error>> {
+    debug!("Creating ZIP of {} entries", file_info_list.len());
 
-    // Sort for deterministic ordering by filename
-    let mut sorted_files = file_info_list.to_vec();
-    sorted_files.sort_by_key(|f| f.filename.clone());
+    let layout = file_info_list.iter().all(|info| {
+        info.zip
+            .as_ref()
+            .is_some_and(|zip| matches!(zip.compression_method, STORED | DEFLATED))
+    });
+    if layout {
+        let mut entries = Vec::with_capacity(file_info_list.len());
+        for file_info in file_info_list {
+            if file_info.filename.ends_with('/') {
+                entries.push((file_info, [].as_slice()));
+            } else if let Some(contents) = parts.get(&file_info.filename) {
+                entries.push((file_info, contents.as_slice()));
+                trace!("Added to ZIP: {}", file_info.filename);
+            } else {
+                warn!("Missing file for ZIP: {}", file_info.filename);
+            }
+        }
+        return write_archive(&entries, comment);
+    }
+    if file_info_list.iter().any(|info| info.zip.is_some()) {
+        warn!("Docx uses an unsupported compression method, rebuilding it with default ZIP headers");
+    }
 
-    let file = File::create(output_docx_path)?;
-    let mut zip = ZipWriter::new(file);
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
 
-    for file_info in sorted_files.iter() {
+    for file_info in file_info_list {
+        let (year, month, day, hour, minute, second) = file_info.datetime;
         // Convert datetime tuple (u16,u16,u16,u16,u16,u16) into zip::DateTime
-        let date_time = match zip::DateTime::from_date_and_time(
-            file_info.datetime.0.try_into().unwrap_or(1980),
-            file_info.datetime.1.try_into().unwrap_or(1),
-            file_info.datetime.2.try_into().unwrap_or(1),
-            file_info.datetime.3.try_into().unwrap_or(0),
-            file_info.datetime.4.try_into().unwrap_or(0),
-            file_info.datetime.5.try_into().unwrap_or(0),
-        ) {
-            Ok(dt) => dt,
-            Err(e) => {
-                println!("Invalid datetime for '{}': {:?}", file_info.filename, e);
-                continue;
-            }
+        let Ok(date_time) = DateTime::from_date_and_time(
+            year,
+            month.try_into().unwrap_or(1),
+            day.try_into().unwrap_or(1),
+            hour.try_into().unwrap_or(0),
+            minute.try_into().unwrap_or(0),
+            second.try_into().unwrap_or(0),
+        ) else {
+            warn!("Invalid datetime for '{}': {:?}", file_info.filename, file_info.datetime);
+            continue;
         };
 
-        let permissions = file_info.unix_permissions;
-
-        let file_path = src_folder.join(&file_info.filename);
-        if !file_path.is_file() {
-            // log_messsage(&format!("Missing file for ZIP: {}", file_path.display()));
+        let Some(contents) = parts.get(&file_info.filename) else {
+            warn!("Missing file for ZIP: {}", file_info.filename);
             continue;
-        }
-
-        let mut contents = Vec::new();
-        File::open(&file_path)?.read_to_end(&mut contents)?;
+        };
 
         let options = FileOptions::default()
             .last_modified_time(date_time)
-            .unix_permissions(permissions);
+            .unix_permissions(file_info.unix_permissions);
 
         zip.start_file(&file_info.filename, options)?;
-        zip.write_all(&contents)?;
-        println!("Added to ZIP: {} as {}", file_path.display(), file_info.filename);
+        zip.write_all(contents)?;
+        trace!("Added to ZIP: {}", file_info.filename);
     }
 
-    zip.finish()?;
-    Ok(())
-}
\ No newline at end of file
+    Ok(zip.finish()?.into_inner())
+}
@@ -0,0 +1,184 @@
+//! Volatile module separates revision identifiers from the XML parts of a docx.
+//!
+//! Word rewrites the `w:rsid*` attributes and the `w:rsids` table of `settings.xml`
+//! on every save, so parts would change even when their text does not. The clean
+//! filter strips them into a side channel stored as the [`RSIDS_PART`] blob of the
+//! docx tree, which lists by part the offsets they were removed at:
+//!
+//! ```text
+//! @word/document.xml
+//! 1042  w:rsidR="00AB12CD"
+//! 1061  w:rsidRDefault="00AB12CD"
+//! ```
+//!
+//! The smudge filter inserts them back at those offsets to rebuild the original bytes.
+//!
+//! Stripping is off unless the [`STRIP_RSIDS_ATTRIBUTE`] attribute is set for a
+//! document in the committed `.gitattributes`, so that every clone stores it the same
+//! way. Documents already committed are stripped by renormalizing them once:
+//!
+//! ```text
+//! $ echo '*.docx docx-strip-rsids' >> .gitattributes
+//! $ git add --renormalize .
+//! ```
+use std::collections::BTreeMap;
+use std::path::Path;
+use git2::{AttrCheckFlags, AttrValue, Repository};
+use log::trace;
+
+/// Git attribute that enables stripping revision identifiers from a document.
+pub const STRIP_RSIDS_ATTRIBUTE: &str = "docx-strip-rsids";
+
+/// Name of the side channel blob at the root of the docx tree.
+pub const RSIDS_PART: &str = ".rsids";
+
+/// Start of a revision identifier attribute.
+const ATTRIBUTE_PREFIX: &[u8] = b" w:rsid";
+
+/// Start tag of the revision identifier table.
+const TABLE_START: &[u8] = b"<w:rsids>";
+
+/// End tag of the revision identifier table.
+const TABLE_END: &[u8] = b"</w:rsids>";
+
+/// Text removed from a part, at its offset in the stripped part.
+type Removal = (usize, Vec<u8>);
+
+/// Returns true if the git attributes of the document at `path` enable stripping revision identifiers.
+#[must_use]
+pub fn strip_enabled(repo: &Repository, path: &str) -> bool {
+    let attribute = repo
+        .get_attr(Path::new(path), STRIP_RSIDS_ATTRIBUTE, AttrCheckFlags::default())
+        .ok()
+        .flatten();
+    AttrValue::from_string(attribute) == AttrValue::True
+}
+
+/// Strips revision identifiers from the XML parts of a docx in place and returns
+/// the side channel needed to restore them, or `None` if there was nothing to strip.
+///
+/// Nothing is stripped if the docx has a part named [`RSIDS_PART`] of its own.
+#[must_use]
+pub fn strip_parts(parts: &mut BTreeMap<String, Vec<u8>>) -> Option<Vec<u8>> {
+    if parts.contains_key(RSIDS_PART) {
+        return None;
+    }
+    let mut side = Vec::new();
+    for (name, contents) in parts.iter_mut() {
+        if !name.to_ascii_lowercase().ends_with(".xml") {
+            continue;
+        }
+        let (stripped, removed) = strip(contents);
+        if removed.is_empty() || restore(&stripped, &removed).as_ref() != Some(contents) {
+            continue;
+        }
+        trace!("Stripped {} revision identifiers from {name}", removed.len());
+        side.extend_from_slice(format!("@{name}\n").as_bytes());
+        for removal in &removed {
+            side.extend_from_slice(removal.0.to_string().as_bytes());
+            side.push(b' ');
+            side.extend_from_slice(&removal.1);
+            side.push(b'\n');
+        }
+        *contents = stripped;
+    }
+    (!side.is_empty()).then_some(side)
+}
+
+/// Inserts the revision identifiers recorded in `side` back into the parts, undoing [`strip_parts`].
+///
+/// # Errors
+///
+/// Returns an error if the side channel is malformed or does not fit the parts.
+pub fn restore_parts(parts: &mut BTreeMap<String, Vec<u8>>, side: &[u8]) -> Result<(), String> {
+    let mut sections: Vec<(String, Vec<Removal>)> = Vec::new();
+    for line in side.split(|byte| *byte == b'\n').filter(|line| !line.is_empty()) {
+        if let Some(name) = line.strip_prefix(b"@") {
+            let part = String::from_utf8(name.to_vec()).map_err(|err| format!("Invalid part name in {RSIDS_PART}: {err}"))?;
+            sections.push((part, Vec::new()));
+            continue;
+        }
+        let invalid = || format!("Invalid line in {RSIDS_PART}: `{}`", String::from_utf8_lossy(line));
+        let space = line.iter().position(|byte| *byte == b' ').ok_or_else(invalid)?;
+        let (digits, text) = line.split_at_checked(space).ok_or_else(invalid)?;
+        let offset = str::from_utf8(digits).ok().and_then(|number| number.parse().ok()).ok_or_else(invalid)?;
+        let section = sections.last_mut().ok_or_else(invalid)?;
+        section.1.push((offset, text.get(1..).unwrap_or_default().to_vec()));
+    }
+
+    for (name, removed) in sections {
+        let contents = parts
+            .get_mut(&name)
+            .ok_or_else(|| format!("{RSIDS_PART} refers to missing part {name}"))?;
+        *contents = restore(contents, &removed).ok_or_else(|| format!("{RSIDS_PART} does not fit part {name}"))?;
+    }
+    Ok(())
+}
+
+/// Removes revision identifier attributes from the tags of `xml` and the revision
+/// identifier table. Returns what is left and the removed text by offset.
+#[must_use]
+pub fn strip(xml: &[u8]) -> (Vec<u8>, Vec<Removal>) {
+    let mut kept = Vec::with_capacity(xml.len());
+    let mut removed = Vec::new();
+    let mut in_tag = false;
+    let mut quote = None;
+    let mut rest = xml;
+    while let Some((byte, tail)) = rest.split_first() {
+        let volatile = match (in_tag, quote) {
+            (true, None) => attribute_len(rest),
+            (false, _) => table_len(rest),
+            (true, Some(_)) => None,
+        };
+        if let Some((text, after)) = volatile.and_then(|len| rest.split_at_checked(len)) {
+            removed.push((kept.len(), text.to_vec()));
+            rest = after;
+            continue;
+        }
+        match (*byte, quote) {
+            (b'"' | b'\'', None) if in_tag => quote = Some(*byte),
+            (_, Some(open)) if open == *byte => quote = None,
+            (b'<', None) => in_tag = true,
+            (b'>', None) => in_tag = false,
+            _ => {}
+        }
+        kept.push(*byte);
+        rest = tail;
+    }
+    (kept, removed)
+}
+
+/// Inserts removed text back at its offsets. Returns `None` if an offset is out of order or range.
+#[must_use]
+pub fn restore(stripped: &[u8], removed: &[Removal]) -> Option<Vec<u8>> {
+    let mut restored = Vec::with_capacity(stripped.len() + removed.iter().map(|removal| removal.1.len()).sum::<usize>());
+    let mut copied = 0;
+    for removal in removed {
+        restored.extend_from_slice(stripped.get(copied..removal.0)?);
+        restored.extend_from_slice(&removal.1);
+        copied = removal.0;
+    }
+    restored.extend_from_slice(stripped.get(copied..)?);
+    Some(restored)
+}
+
+/// Length of the revision identifier attribute `xml` starts with, if any.
+fn attribute_len(xml: &[u8]) -> Option<usize> {
+    let name = xml.strip_prefix(ATTRIBUTE_PREFIX)?;
+    let suffix = name.iter().take_while(|byte| byte.is_ascii_alphabetic()).count();
+    let value = name.get(suffix..)?.strip_prefix(b"=\"")?;
+    let value_len = value.iter().position(|byte| *byte == b'"')?;
+    single_line(value.get(..value_len)?).then_some(ATTRIBUTE_PREFIX.len() + suffix + 2 + value_len + 1)
+}
+
+/// Length of the revision identifier table `xml` starts with, if any.
+fn table_len(xml: &[u8]) -> Option<usize> {
+    let table = xml.strip_prefix(TABLE_START)?;
+    let content_len = table.windows(TABLE_END.len()).position(|window| window == TABLE_END)?;
+    single_line(table.get(..content_len)?).then_some(TABLE_START.len() + content_len + TABLE_END.len())
+}
+
+/// Returns true if `text` can be stored on one line of the side channel.
+fn single_line(text: &[u8]) -> bool {
+    !text.iter().any(|byte| matches!(*byte, b'\n' | b'\r'))
+}
@@ -0,0 +1,128 @@
+//! Identity module decides who docx auto-commits are attributed to and signs them.
+//!
+//! Auto-commits are attributed to the author and committer of the commit that
+//! recorded the document, which is not necessarily `HEAD` when commits are
+//! rewritten, falling back to `user.name` and `user.email`. Both can be
+//! overridden with `docx.committerName` and `docx.committerEmail`.
+//!
+//! Signing follows git's own settings: it is enabled by `docx.signCommits`, or by
+//! `commit.gpgSign` when that is unset, and uses `gpg.format`, `user.signingKey`
+//! and the matching `gpg.program`, `gpg.ssh.program` or `gpg.x509.program`.
+use std::error::Error;
+use std::io::Write as _;
+use std::process::{Command, Stdio};
+use git2::{Commit, Config, Error as GitError, Repository, Signature};
+use log::debug;
+
+/// Git config key overriding the name auto-commits are attributed to.
+pub const COMMITTER_NAME_CONFIG: &str = "docx.committerName";
+
+/// Git config key overriding the email auto-commits are attributed to.
+pub const COMMITTER_EMAIL_CONFIG: &str = "docx.committerEmail";
+
+/// Git config key enabling signing of auto-commits.
+pub const SIGN_COMMITS_CONFIG: &str = "docx.signCommits";
+
+/// Name used when neither the source commit nor git config provide one.
+const FALLBACK_NAME: &str = "docx-git-extension";
+
+/// Email used when neither the source commit nor git config provide one.
+const FALLBACK_EMAIL: &str = "docx-git-extension@localhost";
+
+/// Returns the author and committer of auto-commits made for `source`, timestamped now.
+/// Without a source commit, both are taken from git config.
+///
+/// # Errors
+///
+/// Returns an error if git config cannot be read or a name or email is invalid.
+pub fn author_and_committer(
+    repo: &Repository,
+    source: Option<&Commit<'_>>,
+) -> Result<(Signature<'static>, Signature<'static>), GitError> {
+    let config = repo.config()?;
+    let fallback = repo.signature().ok();
+    let fallback_name = fallback.as_ref().and_then(Signature::name).unwrap_or(FALLBACK_NAME);
+    let fallback_email = fallback.as_ref().and_then(Signature::email).unwrap_or(FALLBACK_EMAIL);
+    let name_override = config.get_string(COMMITTER_NAME_CONFIG).ok();
+    let email_override = config.get_string(COMMITTER_EMAIL_CONFIG).ok();
+
+    let attribute = |recorded: Option<Signature<'_>>| {
+        let name = name_override
+            .as_deref()
+            .or_else(|| recorded.as_ref().and_then(Signature::name))
+            .unwrap_or(fallback_name);
+        let email = email_override
+            .as_deref()
+            .or_else(|| recorded.as_ref().and_then(Signature::email))
+            .unwrap_or(fallback_email);
+        Signature::now(name, email)
+    };
+
+    let author = attribute(source.map(Commit::author))?;
+    let committer = attribute(source.map(Commit::committer))?;
+    Ok((author, committer))
+}
+
+/// Signs a commit buffer if signing is enabled. Returns the signature, or `None`
+/// if auto-commits are not signed.
+///
+/// # Errors
+///
+/// Returns an error if the signing program cannot be run or fails.
+pub fn sign(repo: &Repository, buffer: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let config = repo.config()?;
+    let enabled = config
+        .get_bool(SIGN_COMMITS_CONFIG)
+        .or_else(|_err| config.get_bool("commit.gpgSign"))
+        .unwrap_or(false);
+    if !enabled {
+        return Ok(None);
+    }
+
+    let key = config.get_string("user.signingKey").ok();
+    let format = config.get_string("gpg.format").unwrap_or_default();
+    let (program, args) = match format.as_str() {
+        "ssh" => {
+            let signing_key = key.ok_or("user.signingKey is required to sign with SSH")?;
+            let args = ["-Y", "sign", "-n", "git", "-f"].map(str::to_owned).into_iter().chain([signing_key]);
+            (program(&config, "gpg.ssh.program", "ssh-keygen"), args.collect())
+        }
+        "x509" => (program(&config, "gpg.x509.program", "gpgsm"), gpg_args(key)),
+        _ => (program(&config, "gpg.program", "gpg"), gpg_args(key)),
+    };
+    debug!("Signing auto-commit with {program}");
+
+    let mut child = Command::new(&program)
+        .args(&args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Failed to run {program}: {err}"))?;
+    // Dropping stdin closes it, so the program sees the end of the buffer.
+    child
+        .stdin
+        .take()
+        .ok_or("Failed to open stdin of the signing program")?
+        .write_all(buffer.as_bytes())?;
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(format!("{program} failed to sign: {}", String::from_utf8_lossy(&output.stderr).trim()).into());
+    }
+    Ok(Some(String::from_utf8(output.stdout)?))
+}
+
+/// Reads a signing program from git config.
+fn program(config: &Config, key: &str, default: &str) -> String {
+    config.get_string(key).unwrap_or_else(|_err| default.to_owned())
+}
+
+/// Arguments making gpg or gpgsm write a detached armored signature of stdin to stdout.
+fn gpg_args(key: Option<String>) -> Vec<String> {
+    let mut args = vec!["--status-fd=2".to_owned(), "-bsa".to_owned()];
+    if let Some(signing_key) = key {
+        args.push("-u".to_owned());
+        args.push(signing_key);
+    }
+    args
+}
@@ -1,3 +1,7 @@
-//! This module represents post-commit git hook.
+//! This module represents post-commit git hook, along with the other hooks that
+//! keep the custom references in step with the branches.
 
-pub mod post_commit;
\ No newline at end of file
+pub mod identity;
+#[expect(clippy::module_inception, reason = "Kept for compatibility with existing imports")]
+pub mod post_commit;
+pub mod pre_push;
\ No newline at end of file
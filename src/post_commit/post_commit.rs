@@ -2,132 +2,759 @@
 //! that is created during docx unzip, as well as creating
 //! a custom reference that contains a commit oid.
 
-use git2::{Oid, Repository, Signature, Tree, Delta};
-use std::fs;
+use git2::{Commit, Oid, ObjectType, Repository, Tree, TreeWalkMode, TreeWalkResult, Delta, DiffFindOptions, DiffOptions, Error};
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::BufRead;
 use std::path::Path;
+use std::str::FromStr;
+use log::{debug, error, info, warn};
+use crate::filters::pointer::{Pointer, FormatError};
+use crate::filters::refs::{docx_refname, head_pointers, is_docx_path, tracking_refnames, DOCX_REF_NAMESPACE};
+use crate::post_commit::identity::{author_and_committer, sign};
 
-/// Find and return changed files in two lists: - added/modified and deleted files list.
-pub fn get_modified_docx_files(repo: &Repository) -> (Vec<String>, Vec<String>) {
-    let head = repo.head().unwrap().peel_to_commit().unwrap();
-    let tree = head.tree().unwrap();
+/// Git config key that selects the [`DeletePolicy`].
+pub const DELETE_POLICY_CONFIG: &str = "docx.onDelete";
 
-    let diff = if head.parent_count() == 0 {
-        repo.diff_tree_to_tree(None, Some(&tree), None).unwrap()
-    } else {
-        let parent = head.parent(0).unwrap();
-        let parent_tree = parent.tree().unwrap();
-        repo.diff_tree_to_tree(Some(&parent_tree), Some(&tree), None).unwrap()
-    };
+/// Trailer of auto-commit messages naming the commit that triggered them.
+pub const SOURCE_COMMIT_TRAILER: &str = "Source-Commit";
+
+/// Namespace the references of deleted documents are archived under.
+pub const DELETED_REF_NAMESPACE: &str = "refs/docx-deleted/";
+
+/// Returns the name `refname` is archived under by [`DeletePolicy::Archive`].
+#[must_use]
+pub fn archived_refname(refname: &str) -> String {
+    format!("{DELETED_REF_NAMESPACE}{}", refname.strip_prefix(DOCX_REF_NAMESPACE).unwrap_or(refname))
+}
+
+/// Minimum [`similarity`] in percent of a deleted and an added document for them to be taken as a rename.
+const RENAME_SIMILARITY: u64 = 50;
+
+/// What the post-commit hook does with the reference of a deleted document.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DeletePolicy {
+    /// Move the reference under [`DELETED_REF_NAMESPACE`] with a reflog entry, joining
+    /// it with an earlier archive of the same path (`archive`).
+    #[default]
+    Archive,
+    /// Delete the reference so its trees can be garbage collected (`delete`).
+    Delete,
+    /// Leave the reference in place (`keep`).
+    Keep,
+}
+
+impl DeletePolicy {
+    /// Reads the policy from git config, falling back to the default if unset or invalid.
+    #[must_use]
+    pub fn from_config(repo: &Repository) -> Self {
+        let Ok(value) = repo.config().and_then(|config| config.get_string(DELETE_POLICY_CONFIG)) else {
+            return Self::default();
+        };
+        value.parse().unwrap_or_else(|err| {
+            warn!("{err}, using `{}`", Self::default());
+            Self::default()
+        })
+    }
+}
+
+impl FromStr for DeletePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "archive" => Ok(Self::Archive),
+            "delete" => Ok(Self::Delete),
+            "keep" => Ok(Self::Keep),
+            _ => Err(format!("Unknown {DELETE_POLICY_CONFIG} value `{value}`")),
+        }
+    }
+}
 
-    let mut modified = vec![];
-    let mut deleted = vec![];
-
-    diff.foreach(
-        &mut |delta, _| {
-            let status = delta.status();
-            let path = delta
-                .new_file()
-                .path()
-                .or_else(|| delta.old_file().path())
-                .unwrap();
-
-            if let Some(path_str) = path.to_str() {
-                if path_str.ends_with(".docx") {
-                    match status {
-                        Delta::Modified | Delta::Added => modified.push(path_str.to_string()),
-                        Delta::Deleted => deleted.push(path_str.to_string()),
-                        _ => {}
-                    }
+impl fmt::Display for DeletePolicy {
+    fn fmt(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str(match *self {
+            Self::Archive => "archive",
+            Self::Delete => "delete",
+            Self::Keep => "keep",
+        })
+    }
+}
+
+/// Documents changed by a commit, by repo-relative path.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DocxChanges {
+    /// Added or modified documents.
+    pub modified: Vec<String>,
+    /// Deleted documents.
+    pub deleted: Vec<String>,
+    /// Renamed documents as (old path, new path).
+    pub renamed: Vec<(String, String)>,
+    /// Copied documents as (source path, new path).
+    pub copied: Vec<(String, String)>,
+}
+
+impl DocxChanges {
+    /// Returns the paths of the documents present after the commit: added, modified,
+    /// renamed and copied ones.
+    pub fn paths(&self) -> impl Iterator<Item = String> + '_ {
+        self.modified
+            .iter()
+            .chain(self.renamed.iter().chain(&self.copied).map(|moved| &moved.1))
+            .cloned()
+    }
+}
+
+/// Find and return documents changed by `commit` compared to any of its parents,
+/// detecting renames and copies.
+///
+/// Merge commits are compared against every parent, so documents brought in
+/// from the other side of the merge are reported as well.
+///
+/// # Errors
+///
+/// Returns an error if `commit` cannot be diffed against its parents.
+pub fn get_modified_docx_files(repo: &Repository, commit: &Commit<'_>) -> Result<DocxChanges, Error> {
+    let tree = commit.tree()?;
+
+    let mut changes = DocxChanges::default();
+    if commit.parent_count() == 0 {
+        collect_changes(repo, None, &tree, &mut changes)?;
+    }
+    for parent in commit.parents() {
+        collect_changes(repo, Some(&parent.tree()?), &tree, &mut changes)?;
+    }
+
+    // A document renamed or copied relative to one parent may be added relative to another.
+    let moved: Vec<String> = changes.renamed.iter().chain(&changes.copied).map(|moved_path| moved_path.1.clone()).collect();
+    changes.modified.retain(|path| !moved.contains(path));
+
+    Ok(changes)
+}
+
+/// Adds documents changed between `old_tree` and `new_tree` to `changes`, skipping ones already listed.
+///
+/// Pointers of different documents look alike, so git only pairs a rename or a copy
+/// reliably if the pointer is unchanged or records the same tree. A deleted document
+/// is paired with an added one as well if their unzipped parts are at least
+/// [`RENAME_SIMILARITY`] percent alike, so that a document renamed and edited in the
+/// same commit keeps its history.
+fn collect_changes(repo: &Repository, old_tree: Option<&Tree<'_>>, new_tree: &Tree<'_>, changes: &mut DocxChanges) -> Result<(), Error> {
+    // Unmodified documents are included so copies of them can be detected.
+    let mut options = DiffOptions::new();
+    options.pathspec("*.docx").ignore_case(true).include_unmodified(true);
+    let mut diff = repo.diff_tree_to_tree(old_tree, Some(new_tree), Some(&mut options))?;
+    diff.find_similar(Some(DiffFindOptions::new().renames(true).copies(true).copies_from_unmodified(true)))?;
+
+    let mut added = Vec::new();
+    let mut removed = Vec::new();
+    for delta in diff.deltas() {
+        let old_path = delta.old_file().path().filter(|path| is_docx_path(path)).and_then(Path::to_str);
+        let new_path = delta.new_file().path().filter(|path| is_docx_path(path)).and_then(Path::to_str);
+        let same = same_document(repo, delta.old_file().id(), delta.new_file().id());
+
+        match (delta.status(), old_path, new_path) {
+            (Delta::Renamed, Some(old), Some(new)) if same => push_unique(&mut changes.renamed, (old.to_owned(), new.to_owned())),
+            (Delta::Copied, Some(old), Some(new)) if same => push_unique(&mut changes.copied, (old.to_owned(), new.to_owned())),
+            (Delta::Modified | Delta::Copied, _, Some(path)) => push_unique(&mut changes.modified, path.to_owned()),
+            // A document renamed from or to another file type is an addition or a deletion.
+            (Delta::Added | Delta::Renamed, _, Some(path)) => {
+                added.push((path.to_owned(), delta.new_file().id()));
+                if let (Delta::Renamed, Some(old)) = (delta.status(), old_path) {
+                    removed.push((old.to_owned(), delta.old_file().id()));
                 }
             }
+            (Delta::Deleted | Delta::Renamed, Some(path), _) => removed.push((path.to_owned(), delta.old_file().id())),
+            _ => {}
+        }
+    }
 
-            true
-        },
-        None,
-        None,
-        None,
-    ).unwrap();
+    for (old, old_blob) in removed {
+        let best = added
+            .iter()
+            .enumerate()
+            .map(|(index, candidate)| (similarity(repo, old_blob, candidate.1), index))
+            .filter(|scored| scored.0 >= RENAME_SIMILARITY)
+            .max();
+        match best.map(|scored| added.remove(scored.1)) {
+            Some((new, _new_blob)) => push_unique(&mut changes.renamed, (old, new)),
+            None => push_unique(&mut changes.deleted, old),
+        }
+    }
+    for (path, _blob) in added {
+        push_unique(&mut changes.modified, path);
+    }
+    Ok(())
+}
+
+/// Returns true if two pointer blobs describe the same document: either the pointer was
+/// moved as is, or it records the same docx tree.
+fn same_document(repo: &Repository, old_blob: Oid, new_blob: Oid) -> bool {
+    let tree = |oid| blob_pointer_of(repo, oid)?.tree;
+    old_blob == new_blob || tree(old_blob).is_some_and(|old_tree| tree(new_blob) == Some(old_tree))
+}
+
+/// Returns how alike the documents of two pointer blobs are, in percent.
+///
+/// Documents with the same hash or tree are 100% alike. Otherwise the parts of both
+/// trees are cut after every `>`, so that XML is compared tag by tag, and the score is
+/// the share of bytes in chunks the two documents have in common.
+fn similarity(repo: &Repository, old_blob: Oid, new_blob: Oid) -> u64 {
+    let (Some(old), Some(new)) = (blob_pointer_of(repo, old_blob), blob_pointer_of(repo, new_blob)) else {
+        return 0;
+    };
+    if old.hash == new.hash || (old.tree.is_some() && old.tree == new.tree) {
+        return 100;
+    }
+    let (Some(old_tree), Some(new_tree)) = (old.tree, new.tree) else {
+        return 0;
+    };
+
+    let mut chunks = BTreeMap::<Vec<u8>, usize>::new();
+    let old_size = for_each_chunk(repo, old_tree, |chunk| *chunks.entry(chunk.to_vec()).or_default() += 1);
+    let mut shared = 0;
+    let new_size = for_each_chunk(repo, new_tree, |chunk| {
+        if let Some(count) = chunks.get_mut(chunk).filter(|count| **count > 0) {
+            *count -= 1;
+            shared += chunk.len();
+        }
+    });
+    u64::try_from((shared * 200).checked_div(old_size + new_size).unwrap_or_default()).unwrap_or_default()
+}
 
-    (modified, deleted)
+/// Calls `visit` with every chunk of the blobs in the docx tree `tree`, cut after
+/// every `>`, and returns their total size. Unreadable trees have no chunks.
+fn for_each_chunk<F: FnMut(&[u8])>(repo: &Repository, tree: Oid, mut visit: F) -> usize {
+    let mut blobs = Vec::new();
+    if let Ok(docx_tree) = repo.find_tree(tree) {
+        let walked = docx_tree.walk(TreeWalkMode::PreOrder, |_dir, entry| {
+            if entry.kind() == Some(ObjectType::Blob) {
+                blobs.push(entry.id());
+            }
+            TreeWalkResult::Ok
+        });
+        if walked.is_err() {
+            return 0;
+        }
+    }
+    let mut size = 0;
+    for blob in blobs.into_iter().filter_map(|oid| repo.find_blob(oid).ok()) {
+        size += blob.size();
+        blob.content().split_inclusive(|byte| *byte == b'>').for_each(&mut visit);
+    }
+    size
+}
+
+/// Parses the pointer in the blob `oid`, if it is one.
+fn blob_pointer_of(repo: &Repository, oid: Oid) -> Option<Pointer> {
+    let blob = repo.find_blob(oid).ok()?;
+    Pointer::parse(str::from_utf8(blob.content()).ok()?).ok()
+}
+
+/// Appends `item` unless the list already contains it.
+fn push_unique<T: PartialEq>(list: &mut Vec<T>, item: T) {
+    if !list.contains(&item) {
+        list.push(item);
+    }
 }
 
 /// Read pointer file at specific revision.
-pub fn read_pointer_file_from_commit(repo: &Repository, path: &str) -> Option<String> {
-    let commit = repo.head().unwrap().peel_to_commit().unwrap();
-    let tree = commit.tree().unwrap();
-    if let Ok(entry) = tree.get_path(Path::new(path)) {
-        if let Ok(blob) = repo.find_blob(entry.id()) {
-            return String::from_utf8(blob.content().to_vec()).ok();
+#[must_use]
+pub fn read_pointer_file_from_commit(repo: &Repository, commit: &Commit<'_>, path: &str) -> Option<String> {
+    read_pointer_file_from_tree(repo, &commit.tree().ok()?, path)
+}
+
+/// Read pointer file of a document deleted, renamed or copied by `commit` from the
+/// first of its parents that contains it.
+#[must_use]
+pub fn read_pointer_file_from_parent(repo: &Repository, commit: &Commit<'_>, path: &str) -> Option<String> {
+    commit.parents().find_map(|parent| read_pointer_file_from_tree(repo, &parent.tree().ok()?, path))
+}
+
+/// Read pointer file at `path` within a tree.
+fn read_pointer_file_from_tree(repo: &Repository, tree: &Tree<'_>, path: &str) -> Option<String> {
+    let entry = tree.get_path(Path::new(path)).ok()?;
+    let blob = repo.find_blob(entry.id()).ok()?;
+    String::from_utf8(blob.content().to_vec()).ok()
+}
+
+/// Finds the docx commits holding the versions of the document at `path` recorded by the
+/// parents of `commit`, read from `old_path` in parents where it was not at `path` yet.
+///
+/// The auto-commit of the document is parented on them, so its history follows the
+/// commits that changed it and a merge joins the history of both sides. Versions
+/// already in the history of another one are left out. Pointers that do not record
+/// their tree continue from the current commit of their ref.
+#[must_use]
+pub fn document_history(repo: &Repository, commit: &Commit<'_>, path: &str, old_path: Option<&str>) -> Vec<Oid> {
+    let mut history = Vec::new();
+    for parent in commit.parents() {
+        let Ok(parent_tree) = parent.tree() else {
+            continue;
+        };
+        let recorded = [Some(path), old_path].into_iter().flatten().find_map(|candidate| {
+            let text = read_pointer_file_from_tree(repo, &parent_tree, candidate)?;
+            Some((candidate, Pointer::parse(&text).ok()?))
+        });
+        let Some((recorded_path, pointer)) = recorded else {
+            continue;
+        };
+        let mut refnames = document_refnames(recorded_path, &pointer);
+        // Versions pulled from another clone are only held by the remote-tracking references.
+        let tracking: Vec<String> = refnames.iter().flat_map(|refname| tracking_refnames(repo, refname)).collect();
+        refnames.extend(tracking);
+        // Branches cut before a document was renamed or deleted still record versions that are only archived.
+        let archived: Vec<String> = refnames.iter().map(|refname| archived_refname(refname)).collect();
+        refnames.extend(archived);
+        let found = pointer.tree.map_or_else(
+            || refnames.iter().find_map(|refname| repo.refname_to_id(refname).ok()),
+            |tree| find_docx_commit(repo, tree, &refnames),
+        );
+        match found {
+            Some(oid) => push_unique(&mut history, oid),
+            None => debug!("No docx commit holds {recorded_path} of {}", parent.id()),
         }
     }
-    None
+    independent(repo, &history)
+}
+
+/// Finds a commit holding `tree` in the history of the refs a document may be stored under,
+/// skipping join commits.
+fn find_docx_commit(repo: &Repository, tree: Oid, refnames: &[String]) -> Option<Oid> {
+    let mut walk = repo.revwalk().ok()?;
+    for oid in refnames.iter().filter_map(|refname| repo.refname_to_id(refname).ok()) {
+        walk.push(oid).ok()?;
+    }
+    walk.filter_map(Result::ok)
+        .find(|oid| repo.find_commit(*oid).is_ok_and(|commit| commit.tree_id() == tree && !is_join(&commit)))
+}
+
+/// Returns the commits of `oids` that are not in the history of another one of them.
+fn independent(repo: &Repository, oids: &[Oid]) -> Vec<Oid> {
+    oids.iter()
+        .copied()
+        .filter(|oid| {
+            !oids
+                .iter()
+                .any(|other| other != oid && repo.graph_descendant_of(*other, *oid).unwrap_or_default())
+        })
+        .collect()
 }
 
 /// Parse reference name from pointer file.
-pub fn parse_ref_from_pointer(pointer: &str) -> Option<String> {
-    pointer
-        .lines()
-        .find_map(|line| line.strip_prefix("DOCX-POINTER:").map(|s| s.trim().to_string()))
-}
-
-/// Resolve docx tree from custom reference. 
-pub fn resolve_tree<'a>(repo: &'a Repository, _refname: &str) -> Result<Tree<'a>, Box<dyn std::error::Error>> {
-    let tree_oid_path = Path::new(repo.path()).join("docx-tree-oid");
-    let tree_oid = fs::read_to_string(tree_oid_path)?.trim().to_string();
-    let obj = repo.find_object(Oid::from_str(&tree_oid)?, None)?;
-    let tree = match obj.kind() {
-        // TODO: should work without Tree branch
-        Some(git2::ObjectType::Tree) => obj.peel_to_tree()?,
-        Some(git2::ObjectType::Commit) => obj.peel_to_commit()?.tree()?,
-        _ => return Err(format!("Unexpected object type: {:?}", obj.kind()).into()),
-    };
-    Ok(tree)
+///
+/// # Errors
+///
+/// Returns an error if the pointer file is invalid.
+pub fn parse_ref_from_pointer(pointer: &str) -> Result<String, FormatError> {
+    Pointer::parse(pointer).map(|parsed| parsed.refname)
+}
+
+/// Resolve the docx tree recorded in a pointer file.
+///
+/// # Errors
+///
+/// Returns an error if the pointer does not record a tree or the tree is missing.
+pub fn resolve_tree<'repo>(repo: &'repo Repository, pointer: &Pointer) -> Result<Tree<'repo>, Box<dyn StdError>> {
+    let tree_oid = pointer
+        .tree
+        .ok_or_else(|| format!("Pointer for {} does not record its tree", pointer.refname))?;
+    Ok(repo.find_tree(tree_oid)?)
 }
 
 /// Function that mimics git commit-tree command - creates a commit pointing to the docx tree.
+///
 /// This ensures that the docx tree is referenced and therefore not deleted by garbage collector.
-/// Returns the oid of the created commit.
-pub fn create_commit(repo: &Repository, path: &str, tree: &Tree) -> Option<Oid> {
-    // TODO: author?
-    let author = Signature::now("sbojanic", "sasa-bojanic@hotmail.com").unwrap();
-    let commit_msg = format!("Auto-commit for {} tree", path);
-
-    let parents_refs = repo.revparse_single("HEAD")
-        .ok()
-        .and_then(|obj| obj.into_commit().ok())
-        .map(|commit| vec![commit])
-        .unwrap_or_else(Vec::new);
-
-    match repo.commit(
-        None, // Update HEAD
-        &author,
-        &author,
-        &commit_msg,
-        tree,
-        &parents_refs.iter().collect::<Vec<_>>(),
-    ) {
+/// The commit is parented on the commits in `history`, the docx commits of the versions
+/// recorded by the parents of `source` (see [`document_history`]), so the history of each
+/// document follows the branches that changed it. Its message names `source`, the commit
+/// that recorded the document, and it is attributed to the author and committer of `source`.
+///
+/// If a commit in `history`, or the current commit of `refname`, already holds `tree` and
+/// contains the rest of `history`, it is returned instead of creating an empty commit. The
+/// current commit of `refname` is not reused but parented on if it was made for `rewritten`,
+/// a commit that `source` replaced by an amend or a rebase. Returns the oid of the commit.
+#[must_use]
+pub fn create_commit(
+    repo: &Repository,
+    source: &Commit<'_>,
+    rewritten: Option<Oid>,
+    path: &str,
+    refname: &str,
+    tree: &Tree<'_>,
+    history: &[Oid],
+) -> Option<Oid> {
+    let commit_msg = format!("Auto-commit for {path} tree\n\n{SOURCE_COMMIT_TRAILER}: {}\n", source.id());
+
+    let tip = repo.refname_to_id(refname).ok().and_then(|oid| repo.find_commit(oid).ok());
+    let replaced = tip.as_ref().filter(|commit| rewritten.is_some() && source_commit(commit) == rewritten);
+    let mut candidates = history.to_vec();
+    if let Some(commit) = replaced {
+        debug!("Re-anchoring {refname} from rewritten {} to {}", commit.id(), source.id());
+        push_unique(&mut candidates, commit.id());
+    } else {
+        let contains_history = |holder: Oid| {
+            candidates.iter().all(|oid| *oid == holder || repo.graph_descendant_of(holder, *oid).unwrap_or_default())
+        };
+        let holder = candidates
+            .iter()
+            .copied()
+            .chain(tip.as_ref().map(Commit::id))
+            .find(|oid| {
+                repo.find_commit(*oid).is_ok_and(|commit| commit.tree_id() == tree.id() && !is_join(&commit)) && contains_history(*oid)
+            });
+        if let Some(oid) = holder {
+            debug!("{oid} already holds tree {} of {path}", tree.id());
+            return Some(oid);
+        }
+    }
+
+    let mut parents = Vec::new();
+    for oid in independent(repo, &candidates) {
+        match repo.find_commit(oid) {
+            Ok(commit) => parents.push(commit),
+            Err(err) => warn!("Skipping history {oid} of {path}: {err}"),
+        }
+    }
+
+    match write_commit(repo, Some(source), &commit_msg, tree, &parents.iter().collect::<Vec<_>>()) {
         Ok(oid) => {
-            println!("✅ Created commit {} (HEAD)", oid);
+            info!("Created commit {oid} for {path}");
             Some(oid)
         },
-        Err(e) => {
-            eprintln!("❌ Error creating commit for {}: {}", path, e);
+        Err(err) => {
+            error!("Error creating commit for {path}: {err}");
             None
         },
     }
 }
 
-/// Updates custom reference - writes an oid of previously created commit to the custom reference.
-pub fn update_ref(repo: &Repository, refname: &str, commit_oid: Oid) {
-    if repo.find_reference(refname).is_ok() {
-        if let Err(e) = repo.find_reference(refname).and_then(|mut r| r.delete()) {
-            eprintln!("❌ Failed to delete existing ref {}: {}", refname, e);
-            return;
+/// Returns the commit named by the [`SOURCE_COMMIT_TRAILER`] of an auto-commit.
+#[must_use]
+pub fn source_commit(commit: &Commit<'_>) -> Option<Oid> {
+    commit.message()?.lines().rev().find_map(|line| {
+        let value = line.strip_prefix(SOURCE_COMMIT_TRAILER)?.strip_prefix(':')?;
+        Oid::from_str(value.trim()).ok()
+    })
+}
+
+/// Writes a commit attributed to the author and committer of `source`, or to the identity
+/// configured in git config without one, signing it if enabled. Does not update any reference.
+fn write_commit(
+    repo: &Repository,
+    source: Option<&Commit<'_>>,
+    message: &str,
+    tree: &Tree<'_>,
+    parents: &[&Commit<'_>],
+) -> Result<Oid, Box<dyn StdError>> {
+    let (author, committer) = author_and_committer(repo, source)?;
+    let buffer = repo.commit_create_buffer(&author, &committer, message, tree, parents)?;
+    let content = str::from_utf8(&buffer)?;
+    match sign(repo, content)? {
+        Some(signature) => Ok(repo.commit_signed(content, &signature, None)?),
+        None => Ok(repo.odb()?.write(ObjectType::Commit, &buffer)?),
+    }
+}
+
+/// Points `refname` at `commit_oid` while keeping every commit it held reachable, and
+/// returns the commit it points at afterwards.
+///
+/// Branches that change a document independently anchor versions that do not descend
+/// from each other. If the ref holds such versions, a join commit with the tree of
+/// `commit_oid` is written on top of them and `commit_oid`. Join commits have no
+/// [`SOURCE_COMMIT_TRAILER`], as they record no version of the document, and are
+/// replaced by the next commit that contains everything they join. They are attributed
+/// to `source`, or to the identity configured in git config without one.
+///
+/// # Errors
+///
+/// Returns an error if a commit cannot be read or written or the reference cannot be updated.
+pub fn advance_ref(repo: &Repository, refname: &str, commit_oid: Oid, source: Option<&Commit<'_>>) -> Result<Oid, Box<dyn StdError>> {
+    let contains = |descendant: Oid, ancestor: Oid| descendant == ancestor || repo.graph_descendant_of(descendant, ancestor).unwrap_or_default();
+    let Ok(tip) = repo.refname_to_id(refname) else {
+        repo.reference(refname, commit_oid, false, "Creating DOCX ref")?;
+        return Ok(commit_oid);
+    };
+    if contains(tip, commit_oid) {
+        debug!("{refname} already contains {commit_oid}");
+        return Ok(tip);
+    }
+
+    let mut heads = Vec::new();
+    let mut pending = vec![tip];
+    while let Some(oid) = pending.pop() {
+        let commit = repo.find_commit(oid)?;
+        if is_join(&commit) {
+            pending.extend(commit.parent_ids());
+        } else {
+            push_unique(&mut heads, oid);
+        }
+    }
+    heads.retain(|head| !contains(commit_oid, *head));
+    if heads.iter().any(|head| contains(*head, commit_oid)) {
+        debug!("{refname} already contains {commit_oid}");
+        return Ok(tip);
+    }
+
+    let mut target = commit_oid;
+    if !heads.is_empty() {
+        let commit = repo.find_commit(commit_oid)?;
+        let mut parents = vec![commit.clone()];
+        for head in heads {
+            parents.push(repo.find_commit(head)?);
         }
+        let message = format!("Join histories of {refname}\n");
+        target = write_commit(repo, source, &message, &commit.tree()?, &parents.iter().collect::<Vec<_>>())?;
+        info!("Joined {} histories of {refname} in {target}", parents.len());
     }
+    repo.reference_matching(refname, target, true, tip, "Updating DOCX ref")?;
+    info!("Updated ref {refname} to {target}");
+    Ok(target)
+}
+
+/// Returns true if `commit` is a join commit written by [`advance_ref`].
+fn is_join(commit: &Commit<'_>) -> bool {
+    commit.parent_count() > 1 && source_commit(commit).is_none()
+}
 
-    match repo.reference(refname, commit_oid, true, "Updating DOCX ref") {
-        Ok(_) => println!("✅ Updated ref {} to {}", refname, commit_oid),
-        Err(e) => eprintln!("❌ Failed to update ref {}: {}", refname, e),
+/// Archives or deletes the custom reference of a deleted document according to `policy`.
+///
+/// References still used by a document in `HEAD` are kept: the ref derived from its path,
+/// and the ref named in its pointer if the pointer does not record its tree.
+///
+/// # Errors
+///
+/// Returns an error if `HEAD` cannot be read or a reference cannot be written or deleted.
+pub fn retire_ref(repo: &Repository, path: &str, refname: &str, policy: DeletePolicy) -> Result<(), Box<dyn StdError>> {
+    if policy == DeletePolicy::Keep {
+        debug!("Keeping {refname} of deleted {path}");
+        return Ok(());
     }
-}
\ No newline at end of file
+    if head_pointers(repo)?
+        .iter()
+        .any(|(head_path, pointer)| {
+            (pointer.tree.is_none() && pointer.refname == refname) || docx_refname(head_path).is_ok_and(|derived| derived == refname)
+        })
+    {
+        info!("Keeping {refname} of deleted {path}, another document still uses it");
+        return Ok(());
+    }
+    let Ok(mut reference) = repo.find_reference(refname) else {
+        debug!("{refname} of deleted {path} does not exist");
+        return Ok(());
+    };
+
+    if policy == DeletePolicy::Archive {
+        let target = reference.target().ok_or_else(|| format!("{refname} is a symbolic reference"))?;
+        let archived = archived_refname(refname);
+        let message = format!("Archived {refname} after {path} was deleted");
+        // An earlier archive of the same path is joined rather than overwritten, so its trees stay reachable.
+        let archive_tip = advance_ref(repo, &archived, target, None)?;
+        // Reflogs of refs outside refs/heads are only written with core.logAllRefUpdates=always.
+        let mut reflog = repo.reflog(&archived)?;
+        if reflog.get(0).is_none_or(|entry| entry.message() != Some(message.as_str())) {
+            reflog.append(archive_tip, &author_and_committer(repo, None)?.1, Some(&message))?;
+            reflog.write()?;
+        }
+        info!("Archived {refname} as {archived}");
+    }
+
+    reference.delete()?;
+    info!("Deleted {refname} of deleted {path}");
+    Ok(())
+}
+
+/// Anchors the documents changed by `commit` under their custom references and
+/// retires the references of documents it deleted.
+///
+/// `rewritten` is the commit that `commit` replaced by an amend or a rebase, if any:
+/// documents still anchored for it are re-anchored to `commit`.
+///
+/// # Errors
+///
+/// Returns an error if `commit` cannot be diffed against its parents. Failures to
+/// anchor or retire a single document are logged and do not stop the others.
+pub fn process_commit(repo: &Repository, commit: &Commit<'_>, rewritten: Option<Oid>) -> Result<(), Error> {
+    let changes = get_modified_docx_files(repo, commit)?;
+
+    if changes.modified.is_empty() && changes.renamed.is_empty() && changes.copied.is_empty() {
+        info!("No .docx files added or modified in {}.", commit.id());
+    }
+
+    for path in &changes.modified {
+        info!("Processing {path}...");
+        anchor(repo, commit, rewritten, path, None);
+    }
+
+    // Renamed and copied documents continue the history of their source's ref under the
+    // ref of their new path. The ref of a renamed document is retired like the ref of a
+    // deleted one, once its history is safely anchored under the new ref.
+    let policy = DeletePolicy::from_config(repo);
+    for moved in changes.renamed.iter().chain(&changes.copied) {
+        let (old_path, path) = (moved.0.as_str(), moved.1.as_str());
+        info!("Processing {path} (from {old_path})...");
+        if !anchor(repo, commit, rewritten, path, Some(old_path)) {
+            warn!("Keeping the refs of {old_path}, {path} could not be anchored");
+            continue;
+        }
+        if changes.renamed.contains(moved)
+            && let Some(old_pointer) = parent_pointer(repo, commit, old_path)
+        {
+            for old_refname in document_refnames(old_path, &old_pointer) {
+                if let Err(err) = retire_ref(repo, old_path, &old_refname, policy) {
+                    error!("Error applying `{policy}` policy to {old_refname} after rename to {path}: {err}");
+                }
+            }
+        }
+    }
+
+    for path in &changes.deleted {
+        info!("Processing deleted {path}...");
+        let Some(pointer) = parent_pointer(repo, commit, path) else {
+            continue;
+        };
+        for refname in document_refnames(path, &pointer) {
+            if let Err(err) = retire_ref(repo, path, &refname, policy) {
+                error!("Error applying `{policy}` policy to {refname}: {err}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Processes the commits rewritten by an amend or a rebase.
+///
+/// `input` is the post-rewrite hook input, one `<old-sha> <new-sha> [<extra>]` line per
+/// rewritten commit. Afterwards the documents in `HEAD` that the old or new commits
+/// changed are anchored, as rewriting may reorder the commits that last changed them.
+///
+/// # Errors
+///
+/// Returns an error if the input cannot be read. Failures to process a single commit
+/// are logged and do not stop the others.
+pub fn process_rewrites<R: BufRead>(repo: &Repository, input: R) -> Result<(), Box<dyn StdError>> {
+    let mut paths = BTreeSet::new();
+    for read in input.lines() {
+        let line = read?;
+        let mut shas = line.split_whitespace().map(Oid::from_str);
+        let (Some(Ok(old)), Some(Ok(new))) = (shas.next(), shas.next()) else {
+            warn!("Skipping invalid post-rewrite line `{line}`");
+            continue;
+        };
+        info!("Processing rewrite of {old} to {new}...");
+        if let Ok(commit) = repo.find_commit(old)
+            && let Ok(changes) = get_modified_docx_files(repo, &commit)
+        {
+            paths.extend(changes.paths());
+        }
+        match repo.find_commit(new) {
+            Ok(commit) => {
+                if let Ok(changes) = get_modified_docx_files(repo, &commit) {
+                    paths.extend(changes.paths());
+                }
+                if let Err(err) = process_commit(repo, &commit, Some(old)) {
+                    error!("Failed to diff rewritten commit {new}: {err}");
+                }
+            }
+            Err(err) => error!("Failed to find rewritten commit {new}: {err}"),
+        }
+    }
+
+    if let Err(err) = anchor_head(repo, &paths) {
+        error!("Failed to anchor documents in HEAD: {err}");
+    }
+    Ok(())
+}
+
+/// Anchors the documents at `paths` in `HEAD` whose reference does not hold the tree
+/// their pointer records, as happens when a rebase reorders the commits that changed them.
+///
+/// Pointers that do not record their tree were stored under their reference by the
+/// clean filter of an earlier release and are left as they are.
+///
+/// # Errors
+///
+/// Returns an error if `HEAD` or its pointers cannot be read.
+pub fn anchor_head(repo: &Repository, paths: &BTreeSet<String>) -> Result<(), Box<dyn StdError>> {
+    let head = repo.head()?.peel_to_commit()?;
+    for (path, pointer) in head_pointers(repo)? {
+        if !paths.contains(&path) {
+            continue;
+        }
+        if pointer.tree.is_none() {
+            debug!("Not anchoring {path}, its pointer does not record its tree");
+            continue;
+        }
+        anchor(repo, &head, None, &path, None);
+    }
+    Ok(())
+}
+
+/// Commits the tree of the document at `path` in `commit` on top of its [`document_history`],
+/// read from `old_path` in parents where it was not at `path` yet, and advances the ref
+/// derived from `path` to the commit.
+///
+/// The ref named in the pointer is not used: pointers are kept as they are when their
+/// document is renamed, so it may name the ref of a path the document no longer has.
+///
+/// Returns true if the ref holds the tree of the document afterwards.
+fn anchor(repo: &Repository, commit: &Commit<'_>, rewritten: Option<Oid>, path: &str, old_path: Option<&str>) -> bool {
+    let Some(text) = read_pointer_file_from_commit(repo, commit, path) else {
+        return false;
+    };
+    let pointer = match Pointer::parse(&text) {
+        Ok(pointer) => pointer,
+        Err(err) => {
+            warn!("Invalid docx pointer in {path}: {err}");
+            return false;
+        }
+    };
+    let target = match docx_refname(path) {
+        Ok(refname) => refname,
+        Err(err) => {
+            error!("Error naming ref for {path}: {err}");
+            return false;
+        }
+    };
+    let tree = match resolve_tree(repo, &pointer) {
+        Ok(tree) => tree,
+        Err(err) => {
+            error!("Error resolving tree for {target}: {err}");
+            return false;
+        }
+    };
+
+    let history = document_history(repo, commit, path, old_path);
+    let Some(commit_oid) = create_commit(repo, commit, rewritten, path, &target, &tree, &history) else {
+        return false;
+    };
+    match advance_ref(repo, &target, commit_oid, Some(commit)) {
+        Ok(_) => true,
+        Err(err) => {
+            error!("Failed to update ref {target}: {err}");
+            false
+        }
+    }
+}
+
+/// Returns the refs the document at `path` may be stored under: the ref derived from
+/// its path, then the ref named in its pointer if that is another one.
+fn document_refnames(path: &str, pointer: &Pointer) -> Vec<String> {
+    let mut refnames: Vec<String> = docx_refname(path).ok().into_iter().collect();
+    push_unique(&mut refnames, pointer.refname.clone());
+    refnames
+}
+
+/// Reads and parses the pointer of `path` in the first parent of `commit` that contains it.
+fn parent_pointer(repo: &Repository, commit: &Commit<'_>, path: &str) -> Option<Pointer> {
+    let text = read_pointer_file_from_parent(repo, commit, path)?;
+    match Pointer::parse(&text) {
+        Ok(pointer) => Some(pointer),
+        Err(err) => {
+            warn!("Invalid docx pointer in {path}: {err}");
+            None
+        }
+    }
+}
@@ -0,0 +1,176 @@
+//! Pre-push module sends the custom references of pushed documents along with the commits.
+//!
+//! `git push` only sends the references it is asked to, so without this the smudge
+//! filter of whoever fetches the commits cannot find the trees their pointers record.
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::io::BufRead;
+use std::path::Path;
+use std::process::Command;
+use git2::{Commit, Delta, Oid, Repository};
+use log::{debug, info, warn};
+use crate::filters::pointer::Pointer;
+use crate::filters::refs::{blob_pointer, docx_refname, is_docx_path};
+use crate::filters::remote::fetch_tips;
+use crate::post_commit::post_commit::{advance_ref, archived_refname};
+
+/// Pushes the custom references of the documents in the pushed commits to `remote`.
+///
+/// `input` is the pre-push hook input, one `<local-ref> <local-sha> <remote-ref> <remote-sha>`
+/// line per pushed ref.
+///
+/// # Errors
+///
+/// Returns an error if the input cannot be read, the pushed commits cannot be walked
+/// or the push fails.
+pub fn push_documents<R: BufRead>(repo: &Repository, remote: &str, input: R) -> Result<(), Box<dyn Error>> {
+    let mut updates = Vec::new();
+    for read in input.lines() {
+        let line = read?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match (fields.get(1).map(|sha| Oid::from_str(sha)), fields.get(3).map(|sha| Oid::from_str(sha))) {
+            (Some(Ok(local)), Some(Ok(remote_oid))) => updates.push((local, remote_oid)),
+            _ => warn!("Skipping invalid pre-push line `{line}`"),
+        }
+    }
+    let refs = reachable_refs(repo, remote, &updates)?;
+    join_remote_refs(repo, remote, &refs)?;
+    push_refs(repo, remote, &refs)
+}
+
+/// Joins the history `remote` holds of each of `refs` into the local reference, so
+/// that pushing it is a fast-forward.
+///
+/// Clones extend the history of a document independently, so the remote may hold
+/// versions the local reference does not contain yet. Those are kept reachable from
+/// a join commit, the same way diverging branches are joined by the post-commit hook.
+///
+/// # Errors
+///
+/// Returns an error if `remote` cannot be fetched from or a reference cannot be updated.
+pub fn join_remote_refs(repo: &Repository, remote: &str, refs: &BTreeSet<String>) -> Result<(), Box<dyn Error>> {
+    if refs.is_empty() {
+        return Ok(());
+    }
+    for (refname, remote_tip) in fetch_tips(repo, remote, refs)? {
+        advance_ref(repo, &refname, remote_tip, None)?;
+    }
+    Ok(())
+}
+
+/// Returns the custom references of the documents in pushed commits, limited to
+/// references that exist locally.
+///
+/// `updates` holds the (local, remote) commit of every pushed ref, as git passes them
+/// to the pre-push hook. Commits reachable from a remote commit, or from a
+/// remote-tracking branch of `remote`, are assumed to have been pushed along with
+/// their references before. Deleted refs are skipped.
+///
+/// The archive of each reference under [`DELETED_REF_NAMESPACE`](crate::post_commit::post_commit::DELETED_REF_NAMESPACE)
+/// is included as well, as it holds the trees the pointers of a document recorded
+/// before it was deleted or renamed.
+///
+/// # Errors
+///
+/// Returns an error if the pushed commits cannot be walked or their trees read.
+pub fn reachable_refs(repo: &Repository, remote: &str, updates: &[(Oid, Oid)]) -> Result<BTreeSet<String>, Box<dyn Error>> {
+    let mut walk = repo.revwalk()?;
+    for update in updates {
+        if update.0.is_zero() {
+            continue;
+        }
+        walk.push(update.0)?;
+        if !update.1.is_zero() && repo.find_commit(update.1).is_ok() {
+            walk.hide(update.1)?;
+        }
+    }
+    // Like `git rev-list --not --remotes=<remote>`, so a new branch only walks what the remote lacks.
+    if repo.find_remote(remote).is_ok() {
+        walk.hide_glob(&format!("refs/remotes/{remote}/*"))?;
+    }
+
+    let mut refs = BTreeSet::new();
+    let mut seen = BTreeSet::new();
+    for oid in walk {
+        for (path, pointer) in changed_pointers(repo, &repo.find_commit(oid?)?)? {
+            // Renamed documents are anchored under the ref of their new path before their pointer is cleaned again.
+            let names = [Some(pointer.refname), docx_refname(&path).ok()];
+            for refname in names.into_iter().flatten() {
+                let archived = archived_refname(&refname);
+                for candidate in [refname, archived] {
+                    if seen.insert(candidate.clone()) && repo.find_reference(&candidate).is_ok() {
+                        refs.insert(candidate);
+                    }
+                }
+            }
+        }
+    }
+    debug!("Found {} docx references in pushed commits", refs.len());
+    Ok(refs)
+}
+
+/// Pushes `refs` to `remote` under the same names with `git push`.
+///
+/// The push skips the pre-push hook and does not force. References are joined with
+/// the remote history by [`join_remote_refs`] first, so only those that moved on the
+/// remote in the meantime are rejected rather than overwritten.
+///
+/// # Errors
+///
+/// Returns an error if git cannot be run or the push fails.
+pub fn push_refs(repo: &Repository, remote: &str, refs: &BTreeSet<String>) -> Result<(), Box<dyn Error>> {
+    if refs.is_empty() {
+        debug!("No docx references to push to {remote}");
+        return Ok(());
+    }
+
+    info!("Pushing {} docx references to {remote}", refs.len());
+    let status = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .args(["push", "--no-verify", remote])
+        .args(refs.iter().map(|refname| format!("{refname}:{refname}")))
+        .status()
+        .map_err(|err| format!("Failed to run git push: {err}"))?;
+    if !status.success() {
+        return Err(format!("git push of docx references to {remote} failed with {status}").into());
+    }
+    Ok(())
+}
+
+/// Returns the pointers `commit` adds or changes compared to any of its parents, by path.
+///
+/// Pointers a commit keeps from a parent were pushed along with that parent, so
+/// only the changed ones are read rather than every pointer of the tree.
+fn changed_pointers(repo: &Repository, commit: &Commit<'_>) -> Result<Vec<(String, Pointer)>, Box<dyn Error>> {
+    let tree = commit.tree()?;
+    let mut parent_trees = Vec::new();
+    for parent in commit.parents() {
+        parent_trees.push(Some(parent.tree()?));
+    }
+    if parent_trees.is_empty() {
+        parent_trees.push(None);
+    }
+
+    let mut changed = BTreeMap::new();
+    for parent_tree in parent_trees {
+        let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+        for delta in diff.deltas() {
+            let file = delta.new_file();
+            if delta.status() == Delta::Deleted || !file.path().is_some_and(is_docx_path) {
+                continue;
+            }
+            if let Some(path) = file.path().and_then(Path::to_str) {
+                changed.entry(path.to_owned()).or_insert_with(|| file.id());
+            }
+        }
+    }
+
+    let mut pointers = Vec::new();
+    for (path, oid) in changed {
+        if let Some(pointer) = blob_pointer(repo, &path, oid)? {
+            pointers.push((path, pointer));
+        }
+    }
+    Ok(pointers)
+}
@@ -1 +1,333 @@
-//! TODO: Decide if necessary?
\ No newline at end of file
+//! Cli module parses and runs the command line of the `git-docx` binary.
+//!
+//! With `git-docx` on `PATH` it runs as `git docx <command>`. Global options may
+//! be given before or after the command, up to a `--` separator. The binaries of
+//! earlier releases run their commands through [`execute`] as well.
+use std::env;
+use std::error::Error;
+use std::io::{self, Write};
+use git2::Repository;
+use log::{error, LevelFilter};
+use crate::filters::{clean_filter, process_filter, smudge_filter};
+use crate::filters::inspect::{diff, verify};
+use crate::filters::refs;
+use crate::post_commit::post_commit::{process_commit, process_rewrites};
+use crate::post_commit::pre_push::push_documents;
+use crate::utils::logger;
+use crate::utils::setup::{self, Scope};
+use crate::utils::utils::repo_from_cwd;
+
+/// Version of the extension.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Exit code of a successful run.
+pub const EXIT_SUCCESS: i32 = 0;
+
+/// Exit code when the command fails.
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Exit code when the command line is invalid.
+pub const EXIT_USAGE: i32 = 2;
+
+/// Exit code of `verify` when a document is not rebuilt byte for byte.
+pub const EXIT_UNVERIFIED: i32 = 3;
+
+/// Text printed by `--help`.
+pub const HELP: &str = "\
+Usage: git docx [--verbose | --quiet] <command> [<args>]
+
+Stores docx files in git as trees of their XML parts.
+
+Commands:
+  install [--global]         Configure the filter, .gitattributes, hooks and refspecs
+  uninstall [--global]       Remove what install configured
+  verify [<path>...]         Check that the documents in HEAD are rebuilt byte for byte
+  diff [<from> [<to>]] [-- <path>...]
+                             Show changes to the XML parts of documents, from HEAD
+                             to the working tree by default
+  migrate-refs               Copy legacy refs/docx/<name> refs to path-derived names
+  clean <path>               Clean filter: read a docx on stdin, write its pointer
+  smudge                     Smudge filter: read a pointer on stdin, write the docx
+  process                    Long-running filter process
+  post-commit                post-commit and post-merge hook
+  post-rewrite [<command>]   post-rewrite hook
+  pre-push <remote> [<url>]  pre-push hook
+
+Options:
+  -v, --verbose  Log debug messages
+  -q, --quiet    Log errors only
+  -h, --help     Print this help
+  -V, --version  Print the version
+
+Exit codes:
+  0  Success
+  1  The command failed
+  2  Invalid command line
+  3  verify found documents that are not rebuilt byte for byte
+";
+
+/// Parsed command line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cli {
+    /// Log level set by `--verbose` or `--quiet`, overriding the configured one.
+    pub level: Option<LevelFilter>,
+    /// Command to run.
+    pub command: Command,
+}
+
+/// Commands of the `git-docx` binary.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Print the help.
+    Help,
+    /// Print the version.
+    Version,
+    /// Run the clean filter on the document at `path`.
+    Clean {
+        /// Path of the document, as git passes it with `%f`.
+        path: String,
+    },
+    /// Run the smudge filter.
+    Smudge,
+    /// Run the long-running filter process.
+    Process,
+    /// Run the post-commit hook.
+    PostCommit,
+    /// Run the post-rewrite hook.
+    PostRewrite,
+    /// Run the pre-push hook.
+    PrePush {
+        /// Remote pushed to.
+        remote: String,
+    },
+    /// Configure the extension.
+    Install {
+        /// Configure the global git config instead of the repository.
+        global: bool,
+    },
+    /// Remove the configuration of the extension.
+    Uninstall {
+        /// Remove it from the global git config instead of the repository.
+        global: bool,
+    },
+    /// Copy legacy references to path-derived names.
+    MigrateRefs,
+    /// Check that documents are rebuilt byte for byte.
+    Verify {
+        /// Documents to check, all of them if empty.
+        paths: Vec<String>,
+    },
+    /// Show changes to the XML parts of documents.
+    Diff {
+        /// Revision to compare from, `HEAD` if unset.
+        from: Option<String>,
+        /// Revision to compare to, the working tree if unset.
+        to: Option<String>,
+        /// Documents to compare, all of them if empty.
+        paths: Vec<String>,
+    },
+}
+
+/// Parses command line arguments, without the program name.
+///
+/// # Errors
+///
+/// Returns an error describing the problem if the command is unknown or its arguments are invalid.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Cli, String> {
+    let mut level = None;
+    let mut help = false;
+    let mut version = false;
+    let mut words = Vec::new();
+    let mut separated = false;
+    for arg in args {
+        if separated {
+            words.push(arg);
+            continue;
+        }
+        match arg.as_str() {
+            "-v" | "--verbose" => level = Some(LevelFilter::Debug),
+            "-q" | "--quiet" => level = Some(LevelFilter::Error),
+            "-h" | "--help" => help = true,
+            "-V" | "--version" => version = true,
+            "--" => {
+                separated = true;
+                words.push(arg);
+            }
+            _ => words.push(arg),
+        }
+    }
+
+    let command = if help {
+        Command::Help
+    } else if version {
+        Command::Version
+    } else {
+        command(words)?
+    };
+    Ok(Cli { level, command })
+}
+
+/// Parses a command and its arguments.
+fn command(words: Vec<String>) -> Result<Command, String> {
+    let mut args = words.into_iter();
+    let name = args.next().ok_or("No command given")?;
+    let command = match name.as_str() {
+        "clean" => Command::Clean {
+            path: args.next().ok_or("clean requires the path of the document")?,
+        },
+        "smudge" => Command::Smudge,
+        "process" => Command::Process,
+        // Git passes arguments of its own to hooks, none of which are needed.
+        "post-commit" => return Ok(Command::PostCommit),
+        "post-rewrite" => return Ok(Command::PostRewrite),
+        "pre-push" => {
+            let remote = args.next().ok_or("pre-push requires the name of the remote")?;
+            return Ok(Command::PrePush { remote });
+        }
+        "install" | "uninstall" => {
+            let global = match args.next().as_deref() {
+                None | Some("--local") => false,
+                Some("--global") => true,
+                Some(other) => return Err(format!("Unknown option `{other}` for {name}")),
+            };
+            if name == "install" {
+                Command::Install { global }
+            } else {
+                Command::Uninstall { global }
+            }
+        }
+        "migrate-refs" => Command::MigrateRefs,
+        "verify" => Command::Verify {
+            paths: args.by_ref().filter(|arg| arg != "--").collect(),
+        },
+        "diff" => {
+            let mut revisions = Vec::new();
+            let mut paths = Vec::new();
+            let mut in_paths = false;
+            for arg in args.by_ref() {
+                if in_paths {
+                    paths.push(arg);
+                } else if arg == "--" {
+                    in_paths = true;
+                } else {
+                    revisions.push(arg);
+                }
+            }
+            let mut revision = revisions.into_iter();
+            let (from, to) = (revision.next(), revision.next());
+            if let Some(extra) = revision.next() {
+                return Err(format!("diff takes at most two revisions, got `{extra}` as well"));
+            }
+            Command::Diff { from, to, paths }
+        }
+        _ => return Err(format!("Unknown command `{name}`")),
+    };
+
+    if let Some(extra) = args.next() {
+        return Err(format!("Unexpected argument `{extra}` for {name}"));
+    }
+    Ok(command)
+}
+
+/// Parses `args`, without the program name, and runs the command, writing its
+/// output to `out` and problems with the command line or logging to `err`.
+/// Returns the exit code.
+pub fn execute<I, O, E>(args: I, out: &mut O, err: &mut E) -> i32
+where
+    I: IntoIterator<Item = String>,
+    O: Write,
+    E: Write,
+{
+    let cli = match parse(args) {
+        Ok(cli) => cli,
+        Err(message) => {
+            // Nothing is left to report a failure to write the usage error to.
+            writeln!(err, "git-docx: {message}\n\nRun `git docx --help` for usage.").unwrap_or_default();
+            return EXIT_USAGE;
+        }
+    };
+
+    let repo = repo_from_cwd().ok();
+    if let Err(init_err) = logger::init(repo.as_ref()) {
+        writeln!(err, "Failed to initialize logging: {init_err}").unwrap_or_default();
+    }
+    if let Some(level) = cli.level {
+        log::set_max_level(level);
+    }
+
+    run(cli.command, repo.as_ref(), out).unwrap_or_else(|run_err| {
+        error!("{run_err}");
+        EXIT_FAILURE
+    })
+}
+
+/// Runs a command, writing its output to `out`, and returns the exit code.
+///
+/// # Errors
+///
+/// Returns an error if the command needs a repository and `repo` is `None`, or if it fails.
+pub fn run<W: Write>(command: Command, repo: Option<&Repository>, out: &mut W) -> Result<i32, Box<dyn Error>> {
+    let required = || repo.ok_or("Not a git repository");
+    match command {
+        Command::Help => write!(out, "{HELP}")?,
+        Command::Version => writeln!(out, "git-docx {VERSION}")?,
+        Command::Clean { path } => clean_filter(&path)?,
+        Command::Smudge => smudge_filter()?,
+        Command::Process => process_filter()?,
+        Command::PostCommit => {
+            let repository = required()?;
+            process_commit(repository, &repository.head()?.peel_to_commit()?, None)?;
+        }
+        Command::PostRewrite => process_rewrites(required()?, io::stdin().lock())?,
+        Command::PrePush { remote } => {
+            // Failing to push the references does not stop the push of the branches.
+            if let Err(err) = push_documents(required()?, &remote, io::stdin().lock()) {
+                error!("Failed to push docx references: {err}");
+            }
+        }
+        Command::Install { global } => {
+            let scope = if global { Scope::Global } else { Scope::Local(required()?) };
+            let exe = env::current_exe()?;
+            let bin_dir = exe.parent().ok_or("Cannot find the directory of the executable")?;
+            report(&setup::install(scope, bin_dir)?, "installed", out)?;
+        }
+        Command::Uninstall { global } => {
+            let scope = if global { Scope::Global } else { Scope::Local(required()?) };
+            report(&setup::uninstall(scope)?, "uninstalled", out)?;
+        }
+        Command::MigrateRefs => {
+            let migrated = refs::migrate(required()?)?;
+            writeln!(out, "Migrated {migrated} docx references")?;
+            writeln!(out, "Run `git add --renormalize .` to store documents whose pointers do not record their tree")?;
+        }
+        Command::Verify { paths } => {
+            let mut code = EXIT_SUCCESS;
+            for (path, failure) in verify(required()?, &paths)? {
+                match failure {
+                    None => writeln!(out, "ok      {path}")?,
+                    Some(err) => {
+                        writeln!(out, "FAILED  {path}: {err}")?;
+                        code = EXIT_UNVERIFIED;
+                    }
+                }
+            }
+            return Ok(code);
+        }
+        Command::Diff { from, to, paths } => {
+            let base = from.as_deref().unwrap_or("HEAD");
+            diff(required()?, base, to.as_deref(), &paths, out)?;
+        }
+    }
+    Ok(EXIT_SUCCESS)
+}
+
+/// Writes the changes made by install or uninstall.
+fn report<W: Write>(changes: &[String], done: &str, out: &mut W) -> io::Result<()> {
+    if changes.is_empty() {
+        writeln!(out, "Nothing to change, already {done}")?;
+    }
+    for change in changes {
+        writeln!(out, "{change}")?;
+    }
+    Ok(())
+}
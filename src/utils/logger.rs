@@ -0,0 +1,119 @@
+//! Logger module routes diagnostic messages away from stdout.
+//!
+//! Git captures the stdout of the filters into the pointer blob (clean) or into
+//! the checked out docx (smudge), so everything that is not filter output must
+//! go to stderr or to a log file.
+//!
+//! Verbosity is read from the `DOCX_LOG` environment variable or `docx.logLevel`
+//! git config (`off`, `error`, `warn`, `info`, `debug`, `trace`). The log file is
+//! read from `DOCX_LOG_FILE` or `docx.logFile`; relative paths are resolved
+//! against the `.git` directory. Environment variables take precedence.
+use std::env;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use chrono::Local;
+use git2::Repository;
+use log::{LevelFilter, Log, Metadata, Record, SetLoggerError};
+
+/// Environment variable that sets log verbosity.
+pub const LEVEL_ENV: &str = "DOCX_LOG";
+
+/// Environment variable that sets the log file.
+pub const FILE_ENV: &str = "DOCX_LOG_FILE";
+
+/// Git config key that sets log verbosity.
+pub const LEVEL_CONFIG: &str = "docx.logLevel";
+
+/// Git config key that sets the log file.
+pub const FILE_CONFIG: &str = "docx.logFile";
+
+/// Verbosity used when none is configured.
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::Warn;
+
+/// Logger writing timestamped records to stderr or a log file.
+struct Logger {
+    /// Where the records are written.
+    sink: Mutex<Box<dyn Write + Send>>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let line = format!(
+            "{} {:<5} [{}] {}\n",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+        if let Ok(mut sink) = self.sink.lock() {
+            // There is nowhere left to report a failure to write a log line.
+            sink.write_all(line.as_bytes()).unwrap_or_default();
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut sink) = self.sink.lock() {
+            sink.flush().unwrap_or_default();
+        }
+    }
+}
+
+/// Installs the global logger. Configuration is read from the environment and,
+/// when a repository is given, from its git config.
+///
+/// # Errors
+///
+/// Returns an error if a global logger has already been installed.
+pub fn init(repo: Option<&Repository>) -> Result<(), SetLoggerError> {
+    let config = repo.and_then(|repository| repository.config().ok());
+    let setting = |env_name: &str, config_name: &str| {
+        env::var(env_name)
+            .ok()
+            .or_else(|| config.as_ref()?.get_string(config_name).ok())
+            .filter(|value| !value.trim().is_empty())
+    };
+
+    let level_setting = setting(LEVEL_ENV, LEVEL_CONFIG);
+    let level = level_setting
+        .as_deref()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(DEFAULT_LEVEL);
+
+    let mut file_error = None;
+    let sink: Box<dyn Write + Send> = match setting(FILE_ENV, FILE_CONFIG) {
+        Some(file_setting) => {
+            let mut path = PathBuf::from(file_setting.trim());
+            if let Some(git_dir) = repo.map(Repository::path) && path.is_relative() {
+                path = git_dir.join(path);
+            }
+            match OpenOptions::new().create(true).append(true).open(&path) {
+                Ok(file) => Box::new(file),
+                Err(err) => {
+                    file_error = Some(format!("Cannot open log file {}: {err}", path.display()));
+                    Box::new(io::stderr())
+                }
+            }
+        }
+        None => Box::new(io::stderr()),
+    };
+
+    log::set_boxed_logger(Box::new(Logger { sink: Mutex::new(sink) }))?;
+    log::set_max_level(level);
+
+    if let Some(message) = file_error {
+        log::warn!("{message}, logging to stderr");
+    }
+    if let Some(value) = level_setting.filter(|value| value.trim().parse::<LevelFilter>().is_err()) {
+        log::warn!("Unknown log level `{value}`, using `{DEFAULT_LEVEL}`");
+    }
+    Ok(())
+}
@@ -1,4 +1,7 @@
 //! Utils module implements cli and utility functionalities.
 
 pub mod cli;
+pub mod logger;
+pub mod setup;
+#[expect(clippy::module_inception, reason = "Kept for compatibility with existing imports")]
 pub mod utils;
\ No newline at end of file
@@ -0,0 +1,267 @@
+//! Setup module configures a repository, or the user's global git config, to use the extension.
+//!
+//! Everything is applied idempotently and reported: running `install` again only
+//! repairs what is missing or outdated, and `uninstall` only removes what `install`
+//! writes. Hooks that were not written by `install` are left untouched.
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use git2::{Config, ConfigLevel, Repository};
+use crate::filters::refs::{tracking_refspec, DOCX_REF_NAMESPACE};
+
+/// Line of `.gitattributes` that routes documents through the filter.
+pub const ATTRIBUTES_LINE: &str = "*.docx filter=docx";
+
+/// Line identifying hooks written by `install`.
+const HOOK_MARKER: &str = "# Installed by docx-git-extension";
+
+/// Name of the binary filters and hooks run.
+const BINARY: &str = "git-docx";
+
+/// Installed hooks and the `git-docx` command each of them runs.
+const HOOKS: [(&str, &str); 4] = [
+    ("post-commit", "post-commit"),
+    ("post-merge", "post-commit"),
+    ("post-rewrite", "post-rewrite"),
+    ("pre-push", "pre-push"),
+];
+
+/// Where `install` and `uninstall` apply.
+#[derive(Clone, Copy)]
+pub enum Scope<'repo> {
+    /// The repository's own config, `.gitattributes`, hooks and remotes.
+    Local(&'repo Repository),
+    /// The global git config and attributes file, and the hooks in a globally configured `core.hooksPath`.
+    Global,
+}
+
+/// Configures the filter, `.gitattributes`, hooks and fetch refspecs, running
+/// `git-docx` from `bin_dir`. Returns a description of every change made.
+///
+/// # Errors
+///
+/// Returns an error if git config, the attributes file or a hook cannot be read or written.
+pub fn install(scope: Scope<'_>, bin_dir: &Path) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut changes = Vec::new();
+    let mut config = open_config(scope)?;
+
+    for (key, value) in filter_config(bin_dir) {
+        if config.get_string(key).ok().as_deref() != Some(value.as_str()) {
+            config.set_str(key, &value)?;
+            changes.push(format!("Set {key} to `{value}`"));
+        }
+    }
+
+    let attributes = attributes_path(scope, &config)?;
+    let text = fs::read_to_string(&attributes).unwrap_or_default();
+    if !text.lines().any(|line| line.trim() == ATTRIBUTES_LINE) {
+        let separator = if text.is_empty() || text.ends_with('\n') { "" } else { "\n" };
+        if let Some(parent) = attributes.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&attributes, format!("{text}{separator}{ATTRIBUTES_LINE}\n"))?;
+        changes.push(format!("Added `{ATTRIBUTES_LINE}` to {}", attributes.display()));
+    }
+
+    match hooks_dir(scope)? {
+        Some(dir) => {
+            fs::create_dir_all(&dir)?;
+            let binary = bin_dir.join(executable(BINARY));
+            for (hook, command) in HOOKS {
+                install_hook(&dir.join(hook), &binary, command, &mut changes)?;
+            }
+        }
+        None => changes.push("Skipped hooks: core.hooksPath is not set globally, run `install` in each repository".to_owned()),
+    }
+
+    if let Scope::Local(repo) = scope {
+        for (key, refspec) in refspec_config(repo)? {
+            let mut current = Vec::new();
+            config.multivar(&key, Some(&refspec_pattern()))?.for_each(|entry| {
+                current.extend(entry.value().map(str::to_owned));
+            })?;
+            if current != [refspec.as_str()] {
+                // Refspecs of earlier releases fetched over the local references.
+                if !current.is_empty() {
+                    config.remove_multivar(&key, &refspec_pattern())?;
+                }
+                config.set_multivar(&key, &refspec_pattern(), &refspec)?;
+                changes.push(format!("Added `{refspec}` to {key}"));
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Removes what [`install`] configures. Returns a description of every change made.
+///
+/// # Errors
+///
+/// Returns an error if git config, the attributes file or a hook cannot be read or written.
+pub fn uninstall(scope: Scope<'_>) -> Result<Vec<String>, Box<dyn Error>> {
+    let mut changes = Vec::new();
+    let mut config = open_config(scope)?;
+
+    for (key, _value) in filter_config(Path::new("")) {
+        if config.get_entry(key).is_ok() {
+            config.remove(key)?;
+            changes.push(format!("Removed {key}"));
+        }
+    }
+
+    let attributes = attributes_path(scope, &config)?;
+    if let Ok(text) = fs::read_to_string(&attributes)
+        && text.lines().any(|line| line.trim() == ATTRIBUTES_LINE)
+    {
+        let kept: Vec<&str> = text.lines().filter(|line| line.trim() != ATTRIBUTES_LINE).collect();
+        if kept.iter().all(|line| line.trim().is_empty()) {
+            fs::remove_file(&attributes)?;
+        } else {
+            fs::write(&attributes, format!("{}\n", kept.join("\n")))?;
+        }
+        changes.push(format!("Removed `{ATTRIBUTES_LINE}` from {}", attributes.display()));
+    }
+
+    if let Some(dir) = hooks_dir(scope)? {
+        for (hook, _command) in HOOKS {
+            let path = dir.join(hook);
+            if fs::read_to_string(&path).is_ok_and(|script| script.lines().any(|line| line == HOOK_MARKER)) {
+                fs::remove_file(&path)?;
+                changes.push(format!("Removed {hook} hook {}", path.display()));
+            }
+        }
+    }
+
+    if let Scope::Local(repo) = scope {
+        for (key, refspec) in refspec_config(repo)? {
+            if config.multivar(&key, Some(&refspec_pattern()))?.next().is_some() {
+                config.remove_multivar(&key, &refspec_pattern())?;
+                changes.push(format!("Removed `{refspec}` from {key}"));
+            }
+        }
+    }
+
+    Ok(changes)
+}
+
+/// Opens the config file `scope` writes to.
+fn open_config(scope: Scope<'_>) -> Result<Config, Box<dyn Error>> {
+    match scope {
+        Scope::Local(repo) => Ok(repo.config()?.open_level(ConfigLevel::Local)?),
+        Scope::Global => {
+            let path = match Config::find_global() {
+                Ok(path) => path,
+                Err(_err) => home()?.join(".gitconfig"),
+            };
+            Ok(Config::open(&path)?)
+        }
+    }
+}
+
+/// Filter config keys and the values `install` sets them to.
+fn filter_config(bin_dir: &Path) -> [(&'static str, String); 4] {
+    let binary = quote(&bin_dir.join(executable(BINARY)));
+    [
+        ("filter.docx.clean", format!("{binary} clean %f")),
+        ("filter.docx.smudge", format!("{binary} smudge")),
+        ("filter.docx.process", format!("{binary} process")),
+        ("filter.docx.required", "true".to_owned()),
+    ]
+}
+
+/// Fetch refspec config keys of every remote and the refspec fetching its docx references.
+fn refspec_config(repo: &Repository) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+    Ok(repo
+        .remotes()?
+        .iter()
+        .flatten()
+        .map(|remote| (format!("remote.{remote}.fetch"), tracking_refspec(remote)))
+        .collect())
+}
+
+/// Regular expression matching the refspecs fetching the docx references, including
+/// the `refs/docx/*:refs/docx/*` refspec earlier releases installed.
+fn refspec_pattern() -> String {
+    format!("^\\+?{DOCX_REF_NAMESPACE}\\*:refs/(docx|remotes/[^/]+/docx)/\\*$")
+}
+
+/// Returns the attributes file `scope` routes documents through the filter in.
+fn attributes_path(scope: Scope<'_>, config: &Config) -> Result<PathBuf, Box<dyn Error>> {
+    match scope {
+        Scope::Local(repo) => Ok(repo
+            .workdir()
+            .ok_or("Cannot install into a bare repository")?
+            .join(".gitattributes")),
+        Scope::Global => {
+            if let Ok(path) = config.get_path("core.attributesFile") {
+                return Ok(path);
+            }
+            match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+                Some(dir) => Ok(PathBuf::from(dir).join("git").join("attributes")),
+                None => Ok(home()?.join(".config").join("git").join("attributes")),
+            }
+        }
+    }
+}
+
+/// Returns the directory hooks are installed in, if `scope` has one.
+fn hooks_dir(scope: Scope<'_>) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    match scope {
+        Scope::Local(repo) => match repo.config()?.get_path("core.hooksPath") {
+            Ok(path) if path.is_relative() => Ok(Some(repo.workdir().unwrap_or_else(|| repo.path()).join(path))),
+            Ok(path) => Ok(Some(path)),
+            Err(_err) => Ok(Some(repo.path().join("hooks"))),
+        },
+        Scope::Global => Ok(open_config(scope)?.get_path("core.hooksPath").ok()),
+    }
+}
+
+/// Writes a hook running `command` of `binary`, unless a hook not written by `install` exists.
+fn install_hook(path: &Path, binary: &Path, command: &str, changes: &mut Vec<String>) -> Result<(), Box<dyn Error>> {
+    let script = format!("#!/bin/sh\n{HOOK_MARKER}\nexec {} {command} \"$@\"\n", quote(binary));
+    match fs::read_to_string(path) {
+        Ok(existing) if existing == script => return Ok(()),
+        Ok(existing) if !existing.lines().any(|line| line == HOOK_MARKER) => {
+            changes.push(format!("Skipped existing hook {}, call {} from it", path.display(), binary.display()));
+            return Ok(());
+        }
+        Ok(_) | Err(_) => {}
+    }
+
+    fs::write(path, script)?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt as _;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    changes.push(format!("Installed hook {}", path.display()));
+    Ok(())
+}
+
+/// Returns the file name of a binary on this platform.
+fn executable(name: &str) -> String {
+    format!("{name}{}", env::consts::EXE_SUFFIX)
+}
+
+/// Quotes a path for the shell git runs filters and hooks with.
+fn quote(path: &Path) -> String {
+    let mut quoted = String::from("\"");
+    for character in path.display().to_string().chars() {
+        if matches!(character, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
+        }
+        quoted.push(character);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Returns the home directory of the user.
+fn home() -> Result<PathBuf, Box<dyn Error>> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .ok_or_else(|| "Cannot find the home directory".into())
+}
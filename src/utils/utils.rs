@@ -1,32 +1,61 @@
 //! Module containing utility functions.
 use std::env;
-use std::io::{Read, BufReader};
+use std::fmt::Write as _;
+use std::io::{self, BufReader};
 use git2::{Repository, Error};
-use sha2::{Sha256, Digest};
+use sha2::{Sha256, Digest as _};
 use std::fs::File;
 use std::path::Path;
 
 /// Calculates sha256 of a file at the given path.
-pub fn calculate_sha256<P: AsRef<Path>>(file_path: P) -> std::io::Result<String> {
+///
+/// # Errors
+///
+/// Returns an error if the file cannot be read.
+pub fn calculate_sha256<P: AsRef<Path>>(file_path: P) -> io::Result<String> {
     let file = File::open(file_path)?;
     let mut reader = BufReader::new(file);
     let mut hasher = Sha256::new();
-    let mut buffer = [0u8; 8192];
-
-    loop {
-        let n = reader.read(&mut buffer)?;
-        if n == 0 {
-            break;
-        }
-        hasher.update(&buffer[..n]);
-    }
+    io::copy(&mut reader, &mut hasher)?;
 
     let digest = hasher.finalize();
-    Ok(format!("{:x}", digest))
+    Ok(format!("{digest:x}"))
+}
+
+/// Calculates sha256 of in-memory bytes.
+#[must_use]
+pub fn sha256_of_bytes(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
 }
 
 /// Retuns git2 Reposiotory instance of the current work directory.
+///
+/// # Errors
+///
+/// Returns an error if the current directory is not inside a git repository.
 pub fn repo_from_cwd() -> Result<Repository, Error> {
-    let cwd = env::current_dir().map_err(|e| Error::from_str(&e.to_string()))?;
+    let cwd = env::current_dir().map_err(|err| Error::from_str(&err.to_string()))?;
     Repository::discover(&cwd)
-}
\ No newline at end of file
+}
+
+/// Encodes bytes as a lowercase hex string.
+#[must_use]
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() << 1), |mut hex, byte| {
+        // Writing to a String cannot fail.
+        write!(hex, "{byte:02x}").unwrap_or_default();
+        hex
+    })
+}
+
+/// Decodes a hex string. Returns `None` if it has odd length or a non-hex digit.
+#[must_use]
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
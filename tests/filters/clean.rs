@@ -0,0 +1,119 @@
+use docx_git_extension::filters::clean::{build_tree, unzip};
+use docx_git_extension::filters::guard::ArchiveLimits;
+use docx_git_extension::filters::smudge::{read_tree, MISMATCH_POLICY_CONFIG};
+use docx_git_extension::filters::{clean, smudge};
+use docx_git_extension::filters::pointer::Pointer;
+use docx_git_extension::utils::utils::sha256_of_bytes;
+use git2::{Repository, Signature};
+use std::fs;
+use std::io::{Cursor, Read, Write};
+use std::process::{Command, Stdio};
+use std::path::Path;
+use tempfile::tempdir;
+use zip::write::FileOptions;
+use zip::ZipArchive;
+use super::{archive_with, docx, stage};
+
+#[test]
+fn cleans_staged_bytes_not_working_tree_file() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let staged = docx("staged");
+    // The working tree holds another version, or nothing at all, at the cleaned path.
+    let chapter = dir.path().join("chapter.docx");
+    fs::write(&chapter, docx("working tree")).unwrap();
+    let missing = dir.path().join("missing").join("notes.docx");
+
+    for path in [chapter, missing] {
+        let pointer_bytes = clean(&repo, path.to_str().unwrap(), &staged).unwrap();
+        let pointer = Pointer::parse(std::str::from_utf8(&pointer_bytes).unwrap()).unwrap();
+        assert_eq!(pointer.hash, sha256_of_bytes(&staged));
+        assert_eq!(smudge(&repo, &pointer_bytes).unwrap(), staged);
+    }
+}
+
+#[test]
+fn builds_nested_tree_in_memory() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let bytes = archive_with("", &[("customXml/", FileOptions::default(), b""), ("word/_rels/document.xml.rels", FileOptions::default(), b"<Relationships/>")]);
+
+    let parts = unzip(&bytes, &ArchiveLimits::default()).unwrap();
+    let tree = repo.find_tree(build_tree(&repo, &parts).unwrap()).unwrap();
+    assert!(tree.get_path(Path::new("customXml")).is_ok());
+    let rels = tree.get_path(Path::new("word/_rels/document.xml.rels")).unwrap();
+    assert_eq!(repo.find_blob(rels.id()).unwrap().content(), b"<Relationships/>");
+
+    let files = read_tree(&repo, &tree).unwrap();
+    assert_eq!(files.keys().collect::<Vec<_>>(), ["word/_rels/document.xml.rels"]);
+}
+
+#[test]
+fn smudges_legacy_pointer_through_its_ref() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    repo.config().unwrap().set_str(MISMATCH_POLICY_CONFIG, "reconstruction").unwrap();
+    let cleaned = clean(&repo, "chapter.docx", &docx("legacy")).unwrap();
+    let pointer = Pointer::parse(std::str::from_utf8(&cleaned).unwrap()).unwrap();
+    let signature = Signature::now("T", "t@x").unwrap();
+    let tree = repo.find_tree(pointer.tree.unwrap()).unwrap();
+    let commit = repo.commit(None, &signature, &signature, "docx", &tree, &[]).unwrap();
+    repo.reference("refs/docx/chapter", commit, false, "").unwrap();
+
+    let legacy = format!(
+        "DOCX-POINTER:refs/docx/chapter\nHASH:{}\nMETADATA:[Content_Types].xml|(1980, 1, 1, 0, 0, 0)|420\nMETADATA:word/document.xml|(1980, 1, 1, 0, 0, 0)|420\n",
+        pointer.hash
+    );
+    let rebuilt = smudge(&repo, legacy.as_bytes()).unwrap();
+    let mut archive = ZipArchive::new(Cursor::new(rebuilt)).unwrap();
+    let mut document = String::new();
+    archive.by_name("word/document.xml").unwrap().read_to_string(&mut document).unwrap();
+    assert_eq!(document, "<w:document>legacy</w:document>");
+}
+
+#[test]
+fn keeps_log_output_out_of_filter_output() {
+    let dir = tempdir().unwrap();
+    Repository::init(dir.path()).unwrap();
+    let run = |args: &[&str], env: (&str, &str), input: &[u8]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_git-docx"))
+            .args(args)
+            .current_dir(dir.path())
+            .env("DOCX_LOG", "trace")
+            .env(env.0, env.1)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        output
+    };
+    let original = docx("logged");
+
+    let cleaned = run(&["clean", "chapter.docx"], ("DOCX_LOG_FILE", ""), &original);
+    assert!(Pointer::parse(std::str::from_utf8(&cleaned.stdout).unwrap()).is_ok());
+    assert!(String::from_utf8(cleaned.stderr).unwrap().contains("Cleaning chapter.docx"));
+
+    let smudged = run(&["smudge"], ("DOCX_LOG_FILE", "docx.log"), &cleaned.stdout);
+    assert_eq!(smudged.stdout, original);
+    assert!(smudged.stderr.is_empty());
+    assert!(fs::read_to_string(dir.path().join(".git/docx.log")).unwrap().contains("Hash matched"));
+}
+
+#[test]
+fn keeps_staged_pointer_of_unchanged_document() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let original = clean(&repo, "old.docx", &docx("text")).unwrap();
+
+    // `git mv old.docx new.docx` stages the pointer as is under the new path.
+    stage(&repo, "new.docx", &original);
+
+    assert_eq!(clean(&repo, "new.docx", &docx("text")).unwrap(), original);
+    let edited = clean(&repo, "new.docx", &docx("edited")).unwrap();
+    let pointer = Pointer::parse(std::str::from_utf8(&edited).unwrap()).unwrap();
+    assert_eq!(pointer.refname, "refs/docx/new.docx");
+}
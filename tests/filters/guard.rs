@@ -0,0 +1,64 @@
+use docx_git_extension::filters::clean;
+use docx_git_extension::filters::clean::unzip;
+use docx_git_extension::filters::guard::{ArchiveLimits, MAX_ENTRIES_CONFIG, MAX_RATIO_CONFIG, MAX_SIZE_CONFIG};
+use docx_git_extension::filters::layout::read_archive_info;
+use git2::Repository;
+use tempfile::tempdir;
+use super::archive;
+
+fn rejects(bytes: &[u8], limits: &ArchiveLimits, reason: &str) {
+    let info = read_archive_info(bytes, limits).unwrap_err().to_string();
+    assert!(info.contains(reason), "{info}");
+    let parts = unzip(bytes, limits).unwrap_err().to_string();
+    assert!(parts.contains(reason), "{parts}");
+}
+
+#[test]
+fn rejects_unsafe_entry_names() {
+    let limits = ArchiveLimits::default();
+    for name in ["../../.git/hooks/post-commit", "word/../../evil.xml", "/etc/passwd", "C:/evil.xml", "word\\..\\evil.xml", "word//document.xml", ".git/config"] {
+        let bytes = archive(&[("[Content_Types].xml", b"<Types/>"), (name, b"#!/bin/sh")]);
+        rejects(&bytes, &limits, "ZIP entry name");
+    }
+}
+
+#[test]
+fn checks_names_before_inflating() {
+    let bytes = archive(&[("word/document.xml", &vec![0; 8 << 20]), ("../evil.xml", b"#!/bin/sh")]);
+    rejects(&bytes, &ArchiveLimits::default(), "ZIP entry name");
+}
+
+#[test]
+fn rejects_duplicate_and_conflicting_names() {
+    let limits = ArchiveLimits::default();
+    let duplicate = archive(&[("word/document.xml", b"<a/>"), ("word/document.xml", b"<b/>")]);
+    rejects(&duplicate, &limits, "appears more than once");
+    let conflict = archive(&[("word", b"file"), ("word/document.xml", b"<a/>")]);
+    rejects(&conflict, &limits, "both a file and a directory");
+}
+
+#[test]
+fn enforces_configured_limits() {
+    let bomb = archive(&[("word/document.xml", &vec![0; 8 << 20])]);
+    rejects(&bomb, &ArchiveLimits::default(), "times its compressed size");
+
+    let parts: Vec<(String, Vec<u8>)> = (0..5).map(|index| (format!("part{index}.xml"), vec![b'x'; 1000])).collect();
+    let entries: Vec<(&str, &[u8])> = parts.iter().map(|part| (part.0.as_str(), part.1.as_slice())).collect();
+    let bytes = archive(&entries);
+    assert_eq!(unzip(&bytes, &ArchiveLimits::default()).unwrap().len(), 5);
+    rejects(&bytes, &ArchiveLimits { max_entries: 4, ..ArchiveLimits::default() }, "entries");
+    rejects(&bytes, &ArchiveLimits { max_uncompressed_size: 4500, ..ArchiveLimits::default() }, "bytes at entry");
+
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    assert!(clean(&repo, "doc.docx", &bytes).is_ok());
+    let mut config = repo.config().unwrap();
+    for (key, value) in [(MAX_ENTRIES_CONFIG, "4"), (MAX_SIZE_CONFIG, "4k"), (MAX_RATIO_CONFIG, "0")] {
+        config.set_str(key, value).unwrap();
+    }
+    assert_eq!(
+        ArchiveLimits::from_config(&repo),
+        ArchiveLimits { max_entries: 4, max_uncompressed_size: 4096, ..ArchiveLimits::default() }
+    );
+    assert!(clean(&repo, "doc.docx", &bytes).is_err());
+}
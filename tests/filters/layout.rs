@@ -0,0 +1,48 @@
+use docx_git_extension::filters::guard::ArchiveLimits;
+use docx_git_extension::filters::layout::{read_archive_info, write_archive};
+use std::io::{Cursor, Read};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive};
+use super::archive_with;
+
+fn sample_archive() -> Vec<u8> {
+    archive_with(
+        "archive comment",
+        &[
+            ("mimetype", FileOptions::default().compression_method(CompressionMethod::Stored), b"application/vnd.openxmlformats"),
+            ("word/", FileOptions::default(), b""),
+            (
+                "word/document.xml",
+                FileOptions::default().compression_level(Some(9)).unix_permissions(0o600),
+                "<w:document>Hello</w:document>".repeat(50).as_bytes(),
+            ),
+        ],
+    )
+}
+
+#[test]
+fn rewrites_archive_byte_for_byte() {
+    let original = sample_archive();
+    let info = read_archive_info(&original, &ArchiveLimits::default()).unwrap();
+    assert_eq!(info.files.len(), 3);
+    assert_eq!(info.comment, b"archive comment");
+
+    let mut archive = ZipArchive::new(Cursor::new(&original)).unwrap();
+    let contents: Vec<Vec<u8>> = (0..archive.len())
+        .map(|index| {
+            let mut content = Vec::new();
+            archive.by_index(index).unwrap().read_to_end(&mut content).unwrap();
+            content
+        })
+        .collect();
+    let entries: Vec<_> = info.files.iter().zip(&contents).map(|(file, content)| (file, content.as_slice())).collect();
+
+    assert_eq!(write_archive(&entries, &info.comment).unwrap(), original);
+}
+
+#[test]
+fn rejects_truncated_archive() {
+    let original = sample_archive();
+    assert!(read_archive_info(&original[..original.len() - 30], &ArchiveLimits::default()).is_err());
+    assert!(read_archive_info(b"not a zip", &ArchiveLimits::default()).is_err());
+}
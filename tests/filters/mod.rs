@@ -0,0 +1,87 @@
+mod clean;
+mod guard;
+mod layout;
+mod normalize;
+mod pointer;
+mod process;
+mod refs;
+mod remote;
+mod smudge;
+mod volatile;
+
+use std::io::{Cursor, Write};
+use git2::{IndexEntry, IndexTime, Repository};
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+/// A pointer written verbatim by the first release for `t2/chapter.docx`, log messages included.
+pub const BASELINE_POINTER: &str = "Starting clean_filter
+docx_path: t2/chapter.docx
+DOCX-POINTER:refs/docx/chapter
+DOCX pointer: refs/docx/chapter
+Getting file info from docx: t2/chapter.docx
+FileInfo: [Content_Types].xml datetime=(2026, 10, 17, 7, 26, 56) perms=600
+FileInfo: word/document.xml datetime=(2026, 10, 17, 7, 26, 56) perms=600
+Adding directory to tree: /tmp/.tmpKVvMgK/unzipped
+Added file to tree: /tmp/.tmpKVvMgK/unzipped/[Content_Types].xml
+Adding directory to tree: /tmp/.tmpKVvMgK/unzipped/word
+Added file to tree: /tmp/.tmpKVvMgK/unzipped/word/document.xml
+Added directory to tree: /tmp/.tmpKVvMgK/unzipped/word
+Starting calculate_deterministic_hash
+Added file to ZIP: [Content_Types].xml
+Added file to ZIP: word/document.xml
+Calculated SHA256 hash: c285a3d23a58194f917481983343f1de1d1fafab668d89855cc282a3c4ba4d0b
+HASH:c285a3d23a58194f917481983343f1de1d1fafab668d89855cc282a3c4ba4d0b
+Wrote tree_oid to /tmp/bl/.git/docx-tree-oid
+METADATA:[Content_Types].xml|(2026, 10, 17, 7, 26, 56)|384
+Output METADATA line for [Content_Types].xml
+METADATA:word/document.xml|(2026, 10, 17, 7, 26, 56)|384
+Output METADATA line for word/document.xml
+";
+
+/// Builds a ZIP archive in memory. Entries whose name ends with `/` are added as directories.
+pub fn archive_with(comment: &str, entries: &[(&str, FileOptions, &[u8])]) -> Vec<u8> {
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    zip.set_comment(comment);
+    for (name, options, contents) in entries {
+        if name.ends_with('/') {
+            zip.add_directory(*name, *options).unwrap();
+        } else {
+            zip.start_file(*name, *options).unwrap();
+            zip.write_all(contents).unwrap();
+        }
+    }
+    zip.finish().unwrap().into_inner()
+}
+
+/// Builds a ZIP archive of deflated entries.
+pub fn archive(entries: &[(&str, &[u8])]) -> Vec<u8> {
+    let entries: Vec<_> = entries.iter().map(|(name, contents)| (*name, FileOptions::default(), *contents)).collect();
+    archive_with("", &entries)
+}
+
+/// Builds a minimal docx whose document holds `text`.
+pub fn docx(text: &str) -> Vec<u8> {
+    archive(&[("[Content_Types].xml", b"<Types/>"), ("word/document.xml", format!("<w:document>{text}</w:document>").as_bytes())])
+}
+
+/// Stages `contents` at `path` in the index, as `git add` or `git mv` would after cleaning.
+pub fn stage(repo: &Repository, path: &str, contents: &[u8]) {
+    let mut index = repo.index().unwrap();
+    let entry = IndexEntry {
+        ctime: IndexTime::new(0, 0),
+        mtime: IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode: 0o100644,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id: repo.blob(contents).unwrap(),
+        flags: 0,
+        flags_extended: 0,
+        path: path.as_bytes().to_vec(),
+    };
+    index.add(&entry).unwrap();
+    index.write().unwrap();
+}
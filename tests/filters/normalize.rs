@@ -0,0 +1,78 @@
+use docx_git_extension::filters::normalize::{compact, pretty_print, XmlFormat};
+use docx_git_extension::filters::pointer::Pointer;
+use docx_git_extension::filters::{clean, smudge};
+use git2::Repository;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+use super::{archive, stage};
+
+const DOCUMENT: &str = "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\r\n\
+<w:document><w:body><w:p><w:r><w:t xml:space=\"preserve\">a&gt;b </w:t></w:r><w:r><w:br/><w:t></w:t></w:r></w:p><w:sectPr/></w:body></w:document>";
+
+#[test]
+fn pretty_prints_reversibly() {
+    let pretty = pretty_print(DOCUMENT.as_bytes()).unwrap();
+    assert_eq!(
+        String::from_utf8(pretty.clone()).unwrap(),
+        "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\r\n\
+<w:document>
+  <w:body>
+    <w:p>
+      <w:r>
+        <w:t xml:space=\"preserve\">a&gt;b </w:t>
+      </w:r>
+      <w:r>
+        <w:br/>
+        <w:t>
+        </w:t>
+      </w:r>
+    </w:p>
+    <w:sectPr/>
+  </w:body>
+</w:document>"
+    );
+    assert_eq!(compact(&pretty), DOCUMENT.as_bytes());
+}
+
+#[test]
+fn keeps_parts_that_cannot_be_restored() {
+    // A line break between tags in the original would be removed by compacting.
+    assert_eq!(pretty_print(b"<a>\n  <b/><c/></a>"), None);
+    assert_eq!(pretty_print(b"<a>text</a>"), None);
+}
+
+#[test]
+fn stores_pretty_parts_and_restores_original_bytes() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    fs::write(dir.path().join(".gitattributes"), "*.docx filter=docx docx-xml=pretty\n").unwrap();
+    let docx = archive(&[("word/document.xml", DOCUMENT.as_bytes()), ("word/media/image.png", b"\x89PNG><")]);
+
+    let pointer_bytes = clean(&repo, "doc.docx", &docx).unwrap();
+    let pointer = Pointer::parse(std::str::from_utf8(&pointer_bytes).unwrap()).unwrap();
+    assert_eq!(pointer.pretty.iter().collect::<Vec<_>>(), ["word/document.xml"]);
+    let tree = repo.find_tree(pointer.tree.unwrap()).unwrap();
+    let entry = tree.get_path(Path::new("word/document.xml")).unwrap();
+    assert_eq!(repo.find_blob(entry.id()).unwrap().content(), pretty_print(DOCUMENT.as_bytes()).unwrap());
+
+    assert_eq!(smudge(&repo, &pointer_bytes).unwrap(), docx);
+}
+
+#[test]
+fn reads_format_from_committed_attributes() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let docx = archive(&[("word/document.xml", DOCUMENT.as_bytes())]);
+    let raw = clean(&repo, "doc.docx", &docx).unwrap();
+    stage(&repo, "doc.docx", &raw);
+    assert_eq!(clean(&repo, "doc.docx", &docx).unwrap(), raw);
+
+    // Renormalizing after the attribute changes stores the unchanged document pretty-printed.
+    fs::write(dir.path().join(".gitattributes"), "*.docx docx-xml=pretty\nraw/*.docx docx-xml=raw\n").unwrap();
+    assert_eq!(XmlFormat::from_attributes(&repo, "raw/doc.docx"), XmlFormat::Raw);
+    let pretty = clean(&repo, "doc.docx", &docx).unwrap();
+    assert!(!Pointer::parse(std::str::from_utf8(&pretty).unwrap()).unwrap().pretty.is_empty());
+    stage(&repo, "doc.docx", &pretty);
+    assert_eq!(clean(&repo, "doc.docx", &docx).unwrap(), pretty);
+}
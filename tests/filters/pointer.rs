@@ -0,0 +1,139 @@
+use docx_git_extension::filters::pointer::{FormatError, Pointer, POINTER_VERSION};
+use super::BASELINE_POINTER;
+
+const POINTER: &str = "DOCX-POINTER-VERSION:1
+REF:refs/docx/chapter
+HASH:0123abcd
+METADATA:[Content_Types].xml|(2024, 5, 1, 12, 30, 0)|420
+METADATA:word/a|b.xml|(1980, 1, 1, 0, 0, 0)|0
+";
+
+const POINTER_V2: &str = "DOCX-POINTER-VERSION:2
+REF:refs/docx/chapter
+HASH:0123abcd
+COMMENT:4869
+METADATA:word/a|b.xml|(1980, 1, 1, 0, 0, 0)|420|made=788 needed=20 flags=8 method=8 level=9 internal=0 external=27525120 lextra=5554 cextra= comment=6869 sig=1
+METADATA:mimetype|(2024, 5, 1, 12, 30, 0)|0|made=20 needed=10 flags=0 method=0 internal=1 external=0 lextra= cextra= comment= sig=0
+";
+
+#[test]
+fn parse_and_serialize_round_trip() {
+    let pointer = Pointer::parse(POINTER).unwrap();
+    assert_eq!(pointer.refname, "refs/docx/chapter");
+    assert_eq!(pointer.hash, "0123abcd");
+    assert_eq!(pointer.files.len(), 2);
+    assert_eq!(pointer.serialize().unwrap(), POINTER);
+}
+
+#[test]
+fn zip_attributes_round_trip() {
+    let pointer = Pointer::parse(POINTER_V2).unwrap();
+    assert_eq!(pointer.comment, b"Hi");
+    assert_eq!(pointer.serialize().unwrap(), POINTER_V2);
+
+    let missing = POINTER_V2.replace(" sig=1", "");
+    assert!(matches!(Pointer::parse(&missing), Err(FormatError::InvalidMetadata(_))));
+}
+
+#[test]
+fn tree_round_trip() {
+    let with_tree = POINTER_V2
+        .replacen(":2\n", ":3\n", 1)
+        .replace("HASH:0123abcd\n", "HASH:0123abcd\nTREE:4b825dc642cb6eb9a060e54bf8d69288fbee4904\n");
+    let pointer = Pointer::parse(&with_tree).unwrap();
+    assert_eq!(pointer.tree.unwrap().to_string(), "4b825dc642cb6eb9a060e54bf8d69288fbee4904");
+    assert_eq!(pointer.serialize().unwrap(), with_tree);
+
+    let bad_tree = with_tree.replace("TREE:4b825dc6", "TREE:xyz");
+    assert!(matches!(Pointer::parse(&bad_tree), Err(FormatError::InvalidValue(_))));
+}
+
+#[test]
+fn pretty_parts_round_trip() {
+    let pretty = POINTER_V2
+        .replacen(":2\n", ":4\n", 1)
+        .replace("COMMENT:4869\n", "COMMENT:4869\nPRETTY:word/a|b.xml\n");
+    let pointer = Pointer::parse(&pretty).unwrap();
+    assert_eq!(pointer.pretty.iter().collect::<Vec<_>>(), ["word/a|b.xml"]);
+    assert_eq!(pointer.serialize().unwrap(), pretty);
+
+    let repeated = pretty.replace("PRETTY:word/a|b.xml\n", "PRETTY:word/a|b.xml\nPRETTY:word/a|b.xml\n");
+    assert_eq!(Pointer::parse(&repeated), Err(FormatError::DuplicateField("PRETTY")));
+}
+
+#[test]
+fn rsids_round_trip() {
+    let with_rsids = POINTER_V2
+        .replacen(":2\n", &format!(":{POINTER_VERSION}\n"), 1)
+        .replace("HASH:0123abcd\n", "HASH:0123abcd\nTREE:4b825dc642cb6eb9a060e54bf8d69288fbee4904\nRSIDS:e69de29bb2d1d6434b8b29ae775ad8c2e48c5391\n");
+    let pointer = Pointer::parse(&with_rsids).unwrap();
+    assert_eq!(pointer.rsids.unwrap().to_string(), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+    assert_eq!(pointer.serialize().unwrap(), with_rsids);
+}
+
+#[test]
+fn detects_pointer_input() {
+    assert!(Pointer::is_pointer(POINTER.as_bytes()));
+    assert!(!Pointer::is_pointer(b"PK\x03\x04"));
+}
+
+#[test]
+fn reads_legacy_pointer_as_version_0() {
+    let legacy = POINTER.replacen("DOCX-POINTER-VERSION:1\nREF:", "DOCX-POINTER:", 1);
+    assert!(Pointer::is_pointer(legacy.as_bytes()));
+    let pointer = Pointer::parse(&legacy).unwrap();
+    assert_eq!(pointer.refname, "refs/docx/chapter");
+    assert_eq!(pointer.files.len(), 2);
+    assert_eq!(pointer.serialize().unwrap(), POINTER);
+    assert_eq!(
+        Pointer::parse("DOCX-POINTER:refs/docx/chapter\nDOCX-POINTER:refs/docx/other\nHASH:0123abcd\n"),
+        Err(FormatError::DuplicateField("DOCX-POINTER"))
+    );
+}
+
+#[test]
+fn reads_baseline_pointer_with_log_output() {
+    assert!(Pointer::is_pointer(BASELINE_POINTER.as_bytes()));
+    let pointer = Pointer::parse(BASELINE_POINTER).unwrap();
+    assert_eq!(pointer.refname, "refs/docx/chapter");
+    assert_eq!(pointer.hash, "c285a3d23a58194f917481983343f1de1d1fafab668d89855cc282a3c4ba4d0b");
+    assert_eq!(pointer.tree, None);
+    assert_eq!(pointer.files.len(), 2);
+    let serialized = pointer.serialize().unwrap();
+    assert!(serialized.contains("METADATA:[Content_Types].xml|(2026, 10, 17, 7, 26, 56)|384\n"));
+    assert!(serialized.contains("METADATA:word/document.xml|(2026, 10, 17, 7, 26, 56)|384\n"));
+    assert!(!serialized.contains("Starting clean_filter"));
+}
+
+#[test]
+fn rejects_missing_header() {
+    assert_eq!(Pointer::parse("HASH:0123abcd\n"), Err(FormatError::MissingHeader));
+}
+
+#[test]
+fn rejects_unknown_version() {
+    let next = (POINTER_VERSION + 1).to_string();
+    let future = POINTER.replacen(":1\n", &format!(":{next}\n"), 1);
+    assert_eq!(Pointer::parse(&future), Err(FormatError::UnsupportedVersion(next)));
+}
+
+#[test]
+fn rejects_missing_and_duplicate_fields() {
+    assert_eq!(
+        Pointer::parse("DOCX-POINTER-VERSION:1\nREF:refs/docx/chapter\n"),
+        Err(FormatError::MissingField("HASH"))
+    );
+    assert_eq!(
+        Pointer::parse("DOCX-POINTER-VERSION:1\nREF:a\nREF:b\nHASH:c\n"),
+        Err(FormatError::DuplicateField("REF"))
+    );
+}
+
+#[test]
+fn rejects_log_output_and_malformed_metadata() {
+    let noisy = POINTER.replace("HASH:", "Starting clean_filter\nHASH:");
+    assert!(matches!(Pointer::parse(&noisy), Err(FormatError::UnexpectedLine(_))));
+
+    let bad_date = POINTER.replace("(1980, 1, 1, 0, 0, 0)", "(1980, 1, 1)");
+    assert!(matches!(Pointer::parse(&bad_date), Err(FormatError::InvalidMetadata(_))));
+}
@@ -0,0 +1,164 @@
+use docx_git_extension::filters::pkt_line::{
+    read_content, read_packet, read_text_list, write_content, write_flush, write_text, Packet,
+    MAX_DATA_LEN,
+};
+use docx_git_extension::filters::process;
+use git2::Repository;
+use std::io::Cursor;
+use tempfile::tempdir;
+
+fn handshake(input: &mut Vec<u8>) {
+    write_text(input, "git-filter-client").unwrap();
+    write_text(input, "version=2").unwrap();
+    write_flush(input).unwrap();
+    write_text(input, "capability=clean").unwrap();
+    write_text(input, "capability=smudge").unwrap();
+    write_text(input, "capability=delay").unwrap();
+    write_flush(input).unwrap();
+}
+
+fn request(input: &mut Vec<u8>, command: &str, pathname: &str, content: &[u8]) {
+    write_text(input, &format!("command={command}")).unwrap();
+    write_text(input, &format!("pathname={pathname}")).unwrap();
+    write_flush(input).unwrap();
+    write_content(input, content).unwrap();
+}
+
+fn delayable_request(input: &mut Vec<u8>, pathname: &str, content: &[u8]) {
+    write_text(input, "command=smudge").unwrap();
+    write_text(input, &format!("pathname={pathname}")).unwrap();
+    write_text(input, "can-delay=1").unwrap();
+    write_flush(input).unwrap();
+    write_content(input, content).unwrap();
+}
+
+fn list_available_blobs(input: &mut Vec<u8>) {
+    write_text(input, "command=list_available_blobs").unwrap();
+    write_flush(input).unwrap();
+}
+
+#[test]
+fn content_is_split_into_packets() {
+    let content = vec![7; MAX_DATA_LEN + 10];
+    let mut buffer = Vec::new();
+    write_content(&mut buffer, &content).unwrap();
+
+    let mut reader = Cursor::new(buffer);
+    assert_eq!(read_content(&mut reader).unwrap(), content);
+    assert_eq!(read_packet(&mut reader).unwrap(), None);
+}
+
+#[test]
+fn serves_requests_until_input_is_closed() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+
+    let mut input = Vec::new();
+    handshake(&mut input);
+    request(&mut input, "smudge", "notes.docx", b"not a pointer");
+    request(&mut input, "frobnicate", "notes.docx", b"");
+
+    let mut output = Vec::new();
+    process::run(&repo, &mut Cursor::new(input), &mut output).unwrap();
+
+    let mut reader = Cursor::new(output);
+    assert_eq!(
+        read_text_list(&mut reader).unwrap().unwrap(),
+        ["git-filter-server", "version=2"]
+    );
+    assert_eq!(
+        read_text_list(&mut reader).unwrap().unwrap(),
+        ["capability=clean", "capability=smudge", "capability=delay"]
+    );
+
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=success"]);
+    assert_eq!(read_content(&mut reader).unwrap(), b"not a pointer");
+    assert_eq!(read_packet(&mut reader).unwrap(), Some(Packet::Flush));
+
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=error"]);
+    assert_eq!(read_packet(&mut reader).unwrap(), None);
+}
+
+#[test]
+fn delays_smudge_until_blobs_are_listed() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+
+    let mut input = Vec::new();
+    handshake(&mut input);
+    delayable_request(&mut input, "a.docx", b"first");
+    delayable_request(&mut input, "b.docx", b"second");
+    // Git keeps asking until every delayed blob has been listed.
+    list_available_blobs(&mut input);
+    list_available_blobs(&mut input);
+    list_available_blobs(&mut input);
+    request(&mut input, "smudge", "b.docx", b"");
+    request(&mut input, "smudge", "a.docx", b"");
+
+    let mut output = Vec::new();
+    process::run(&repo, &mut Cursor::new(input), &mut output).unwrap();
+
+    let mut reader = Cursor::new(output);
+    read_text_list(&mut reader).unwrap();
+    read_text_list(&mut reader).unwrap();
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=delayed"]);
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=delayed"]);
+
+    let mut listed = Vec::new();
+    for _ in 0..3 {
+        listed.extend(read_text_list(&mut reader).unwrap().unwrap());
+        assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=success"]);
+    }
+    listed.sort();
+    assert_eq!(listed, ["pathname=a.docx", "pathname=b.docx"]);
+
+    for expected in [&b"second"[..], &b"first"[..]] {
+        assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=success"]);
+        assert_eq!(read_content(&mut reader).unwrap(), expected);
+        assert_eq!(read_packet(&mut reader).unwrap(), Some(Packet::Flush));
+    }
+    assert_eq!(read_packet(&mut reader).unwrap(), None);
+}
+
+#[test]
+fn rejects_unknown_delayed_blob() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+
+    let mut input = Vec::new();
+    handshake(&mut input);
+    delayable_request(&mut input, "a.docx", b"first");
+    list_available_blobs(&mut input);
+    request(&mut input, "smudge", "a.docx", b"");
+    // Asking again, or for a blob that was never delayed, must not write an empty file.
+    request(&mut input, "smudge", "a.docx", b"");
+    request(&mut input, "smudge", "b.docx", b"");
+
+    let mut output = Vec::new();
+    process::run(&repo, &mut Cursor::new(input), &mut output).unwrap();
+
+    let mut reader = Cursor::new(output);
+    read_text_list(&mut reader).unwrap();
+    read_text_list(&mut reader).unwrap();
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=delayed"]);
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["pathname=a.docx"]);
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=success"]);
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=success"]);
+    assert_eq!(read_content(&mut reader).unwrap(), b"first");
+    assert_eq!(read_packet(&mut reader).unwrap(), Some(Packet::Flush));
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=error"]);
+    assert_eq!(read_text_list(&mut reader).unwrap().unwrap(), ["status=error"]);
+    assert_eq!(read_packet(&mut reader).unwrap(), None);
+}
+
+#[test]
+fn rejects_unknown_welcome() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+
+    let mut input = Vec::new();
+    write_text(&mut input, "git-lfs-client").unwrap();
+    write_flush(&mut input).unwrap();
+
+    assert!(process::run(&repo, &mut Cursor::new(input), &mut Vec::new()).is_err());
+}
@@ -0,0 +1,64 @@
+use docx_git_extension::filters::refs::{docx_refname, migrate};
+use git2::{Repository, Signature};
+use tempfile::tempdir;
+use super::BASELINE_POINTER;
+
+#[test]
+fn refnames_are_unique_per_path() {
+    assert_eq!(docx_refname("chapter.docx").unwrap(), "refs/docx/chapter.docx");
+    assert_eq!(docx_refname("title1/chapter.docx").unwrap(), "refs/docx/title1%2Fchapter.docx");
+    assert_ne!(docx_refname("title1/chapter.docx").unwrap(), docx_refname("title2/chapter.docx").unwrap());
+    assert_eq!(docx_refname(".hidden..docx").unwrap(), "refs/docx/%2Ehidden.%2Edocx");
+    assert_eq!(docx_refname("my doc~1.lock").unwrap(), "refs/docx/my%20doc%7E1%2Elock");
+    assert_eq!(docx_refname("a%2F.docx").unwrap(), "refs/docx/a%252F.docx");
+}
+
+#[test]
+fn migrates_legacy_refs() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let signature = Signature::now("T", "t@x").unwrap();
+    let empty_tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+    let docx_commit = repo.commit(None, &signature, &signature, "docx", &empty_tree, &[]).unwrap();
+    repo.reference("refs/docx/report", docx_commit, false, "").unwrap();
+    repo.reference("refs/docx/chapter", docx_commit, false, "").unwrap();
+
+    let pointer = |name: &str| format!("DOCX-POINTER-VERSION:1\nREF:refs/docx/{name}\nHASH:00\n");
+    let mut title1 = repo.treebuilder(None).unwrap();
+    title1.insert("chapter.docx", repo.blob(pointer("chapter").as_bytes()).unwrap(), 0o100644).unwrap();
+    let mut title2 = repo.treebuilder(None).unwrap();
+    title2.insert("chapter.docx", repo.blob(pointer("chapter").as_bytes()).unwrap(), 0o100644).unwrap();
+    let mut root = repo.treebuilder(None).unwrap();
+    root.insert("report.docx", repo.blob(pointer("report").as_bytes()).unwrap(), 0o100644).unwrap();
+    root.insert("title1", title1.write().unwrap(), 0o040000).unwrap();
+    root.insert("title2", title2.write().unwrap(), 0o040000).unwrap();
+    let tree = repo.find_tree(root.write().unwrap()).unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "docs", &tree, &[]).unwrap();
+
+    // The shared `refs/docx/chapter` cannot tell the two chapters apart, so only the report moves.
+    assert_eq!(migrate(&repo).unwrap(), 1);
+    assert_eq!(repo.refname_to_id("refs/docx/report.docx").unwrap(), docx_commit);
+    assert!(repo.find_reference("refs/docx/title1%2Fchapter.docx").is_err());
+    assert_eq!(migrate(&repo).unwrap(), 0);
+}
+
+#[test]
+fn migrates_baseline_pointers() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let signature = Signature::now("T", "t@x").unwrap();
+    let empty_tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+    let docx_commit = repo.commit(None, &signature, &signature, "docx", &empty_tree, &[]).unwrap();
+    repo.reference("refs/docx/chapter", docx_commit, false, "").unwrap();
+
+    // The first release named references after the basename and wrote its log into the pointer.
+    let mut docs = repo.treebuilder(None).unwrap();
+    docs.insert("chapter.docx", repo.blob(BASELINE_POINTER.as_bytes()).unwrap(), 0o100644).unwrap();
+    let mut root = repo.treebuilder(None).unwrap();
+    root.insert("t2", docs.write().unwrap(), 0o040000).unwrap();
+    let tree = repo.find_tree(root.write().unwrap()).unwrap();
+    repo.commit(Some("HEAD"), &signature, &signature, "docs", &tree, &[]).unwrap();
+
+    assert_eq!(migrate(&repo).unwrap(), 1);
+    assert_eq!(repo.refname_to_id("refs/docx/t2%2Fchapter.docx").unwrap(), docx_commit);
+}
@@ -0,0 +1,55 @@
+use docx_git_extension::filters::pointer::Pointer;
+use docx_git_extension::filters::remote::{fetch_documents, AUTO_FETCH_CONFIG};
+use docx_git_extension::filters::smudge::{create_docx_from_commit, MismatchPolicy};
+use git2::{Repository, Signature};
+use tempfile::tempdir;
+
+#[test]
+fn smudge_fetches_missing_ref_from_remote() {
+    let dir = tempdir().unwrap();
+    let remote = Repository::init_bare(dir.path().join("remote.git")).unwrap();
+    let signature = Signature::now("T", "t@x").unwrap();
+    let mut docx = remote.treebuilder(None).unwrap();
+    docx.insert("document.xml", remote.blob(b"<w:document/>").unwrap(), 0o100644).unwrap();
+    let tree = remote.find_tree(docx.write().unwrap()).unwrap();
+    let docx_commit = remote.commit(None, &signature, &signature, "docx", &tree, &[]).unwrap();
+    remote.reference("refs/docx/doc.docx", docx_commit, false, "").unwrap();
+    remote.reference("refs/docx/other.docx", docx_commit, false, "").unwrap();
+
+    let repo = Repository::init(dir.path().join("clone")).unwrap();
+    repo.remote("origin", dir.path().join("remote.git").to_str().unwrap()).unwrap();
+    let pointer = Pointer::parse(&format!("DOCX-POINTER-VERSION:3\nREF:refs/docx/doc.docx\nHASH:00\nTREE:{}\n", tree.id())).unwrap();
+
+    repo.config().unwrap().set_bool(AUTO_FETCH_CONFIG, false).unwrap();
+    assert!(create_docx_from_commit(&repo, &pointer, MismatchPolicy::Reconstruction).is_err());
+
+    repo.config().unwrap().set_bool(AUTO_FETCH_CONFIG, true).unwrap();
+    assert!(create_docx_from_commit(&repo, &pointer, MismatchPolicy::Reconstruction).is_ok());
+    assert_eq!(repo.refname_to_id("refs/remotes/origin/docx/doc.docx").unwrap(), docx_commit);
+    assert_eq!(repo.refname_to_id("refs/remotes/origin/docx/other.docx").unwrap(), docx_commit);
+    assert!(repo.find_reference("refs/docx/doc.docx").is_err());
+
+    // Pointers without a tree resolve through the remote-tracking reference.
+    let untracked = Pointer::parse("DOCX-POINTER-VERSION:3\nREF:refs/docx/other.docx\nHASH:00\n").unwrap();
+    assert!(create_docx_from_commit(&repo, &untracked, MismatchPolicy::Reconstruction).is_ok());
+
+    // The namespace is fetched once per process, so later refs are not fetched again.
+    remote.reference("refs/docx/later.docx", docx_commit, false, "").unwrap();
+    let later = Pointer::parse("DOCX-POINTER-VERSION:3\nREF:refs/docx/later.docx\nHASH:00\n").unwrap();
+    assert!(create_docx_from_commit(&repo, &later, MismatchPolicy::Reconstruction).is_err());
+    fetch_documents(&repo).unwrap();
+    assert!(repo.find_reference("refs/remotes/origin/docx/later.docx").is_err());
+}
+
+#[test]
+fn retries_fetch_after_failure() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path().join("work")).unwrap();
+    let url = dir.path().join("remote.git");
+    repo.remote("origin", url.to_str().unwrap()).unwrap();
+
+    assert!(fetch_documents(&repo).is_err());
+    // The remote becomes reachable, so the failure must not be remembered.
+    Repository::init_bare(&url).unwrap();
+    fetch_documents(&repo).unwrap();
+}
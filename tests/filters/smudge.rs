@@ -0,0 +1,73 @@
+use docx_git_extension::filters::pointer::Pointer;
+use docx_git_extension::filters::smudge::{create_docx_from_commit, MismatchPolicy, WARNINGS_FILE};
+use docx_git_extension::filters::{clean, smudge};
+use git2::{Repository, Signature};
+use std::fs;
+use tempfile::tempdir;
+use std::io::{Cursor, Read};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive};
+use super::{archive_with, docx};
+
+fn cleaned(repo: &Repository, text: &str) -> Pointer {
+    let pointer_bytes = clean(repo, "doc.docx", &docx(text)).unwrap();
+    Pointer::parse(std::str::from_utf8(&pointer_bytes).unwrap()).unwrap()
+}
+
+#[test]
+fn applies_policy_on_hash_mismatch() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let mut pointer = cleaned(&repo, "text");
+    let rebuilt = create_docx_from_commit(&repo, &pointer, MismatchPolicy::Fail).unwrap();
+    pointer.hash = "00".to_owned();
+
+    let err = create_docx_from_commit(&repo, &pointer, MismatchPolicy::Fail).unwrap_err();
+    assert!(err.to_string().contains("Hash mismatch"));
+    assert_eq!(create_docx_from_commit(&repo, &pointer, MismatchPolicy::Reconstruction).unwrap(), rebuilt);
+    assert_eq!(
+        create_docx_from_commit(&repo, &pointer, MismatchPolicy::Pointer).unwrap(),
+        pointer.serialize().unwrap().into_bytes()
+    );
+
+    let warnings = fs::read_to_string(repo.path().join(WARNINGS_FILE)).unwrap();
+    assert_eq!(warnings.lines().count(), 3);
+    assert!(warnings.lines().all(|line| line.contains("Expected: 00")));
+}
+
+#[test]
+fn rebuilds_historical_version_after_ref_moved_on() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let signature = Signature::now("T", "t@x").unwrap();
+    let first = cleaned(&repo, "first");
+    let second = cleaned(&repo, "second");
+    let latest = repo.find_tree(second.tree.unwrap()).unwrap();
+    let commit = repo.commit(None, &signature, &signature, "docx", &latest, &[]).unwrap();
+    repo.reference(&first.refname, commit, false, "").unwrap();
+
+    assert_eq!(smudge(&repo, first.serialize().unwrap().as_bytes()).unwrap(), docx("first"));
+    assert_eq!(smudge(&repo, second.serialize().unwrap().as_bytes()).unwrap(), docx("second"));
+}
+
+#[test]
+fn rebuilds_unsupported_compression_method_with_zip_crate() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let text = b"<w:document>bzip2</w:document>";
+    let original = archive_with(
+        "",
+        &[
+            ("[Content_Types].xml", FileOptions::default(), b"<Types/>"),
+            ("word/document.xml", FileOptions::default().compression_method(CompressionMethod::Bzip2), text),
+        ],
+    );
+    let pointer_bytes = clean(&repo, "doc.docx", &original).unwrap();
+    let pointer = Pointer::parse(std::str::from_utf8(&pointer_bytes).unwrap()).unwrap();
+
+    let rebuilt = create_docx_from_commit(&repo, &pointer, MismatchPolicy::Reconstruction).unwrap();
+    let mut archive = ZipArchive::new(Cursor::new(rebuilt)).unwrap();
+    let mut content = Vec::new();
+    archive.by_name("word/document.xml").unwrap().read_to_end(&mut content).unwrap();
+    assert_eq!(content, text);
+}
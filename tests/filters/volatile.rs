@@ -0,0 +1,96 @@
+use docx_git_extension::filters::pointer::Pointer;
+use docx_git_extension::filters::volatile::{restore, strip, RSIDS_PART, STRIP_RSIDS_ATTRIBUTE};
+use docx_git_extension::filters::{clean, smudge};
+use git2::Repository;
+use std::fs;
+use std::path::Path;
+use tempfile::tempdir;
+use super::{archive, stage};
+
+fn document(rsid: &str) -> String {
+    format!(
+        "<w:document><w:body><w:p w:rsidR=\"{rsid}\" w:rsidRDefault=\"{rsid}\"><w:r w:rsidRPr=\"{rsid}\">\
+<w:t xml:space=\"preserve\">Text w:rsidR=\"kept\"</w:t></w:r></w:p><w:sectPr w:rsidR=\"{rsid}\"/></w:body></w:document>"
+    )
+}
+
+fn settings(rsid: &str) -> String {
+    format!("<w:settings><w:zoom w:percent=\"100\"/><w:rsids><w:rsidRoot w:val=\"00A1\"/><w:rsid w:val=\"{rsid}\"/></w:rsids></w:settings>")
+}
+
+fn docx(rsid: &str, extra: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut entries = vec![("word/document.xml", document(rsid).into_bytes()), ("word/settings.xml", settings(rsid).into_bytes())];
+    entries.extend(extra.iter().map(|(name, contents)| (*name, contents.to_vec())));
+    archive(&entries.iter().map(|(name, contents)| (*name, contents.as_slice())).collect::<Vec<_>>())
+}
+
+#[test]
+fn strips_attributes_and_table() {
+    let (stripped, removed) = strip(document("00B2").as_bytes());
+    assert_eq!(
+        String::from_utf8(stripped.clone()).unwrap(),
+        "<w:document><w:body><w:p><w:r><w:t xml:space=\"preserve\">Text w:rsidR=\"kept\"</w:t></w:r></w:p><w:sectPr/></w:body></w:document>"
+    );
+    assert_eq!(removed.len(), 4);
+    assert_eq!(restore(&stripped, &removed).unwrap(), document("00B2").as_bytes());
+
+    let (stripped, removed) = strip(settings("00B2").as_bytes());
+    assert_eq!(stripped, b"<w:settings><w:zoom w:percent=\"100\"/></w:settings>");
+    assert_eq!(restore(&stripped, &removed).unwrap(), settings("00B2").as_bytes());
+}
+
+#[test]
+fn keeps_content_blobs_stable_across_saves() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let blob = |tree: &git2::Tree<'_>, path: &str| tree.get_path(Path::new(path)).map(|entry| entry.id()).ok();
+
+    let mut trees = Vec::new();
+    for (rsid, format) in [("00B2", "raw"), ("00C3", "raw"), ("00D4", "pretty")] {
+        fs::write(dir.path().join(".gitattributes"), format!("*.docx docx-xml={format} {STRIP_RSIDS_ATTRIBUTE}\n")).unwrap();
+        let original = docx(rsid, &[]);
+        let pointer_bytes = clean(&repo, "doc.docx", &original).unwrap();
+        let pointer = Pointer::parse(std::str::from_utf8(&pointer_bytes).unwrap()).unwrap();
+        let tree = repo.find_tree(pointer.tree.unwrap()).unwrap();
+        assert_eq!(blob(&tree, RSIDS_PART), pointer.rsids);
+        assert_eq!(smudge(&repo, &pointer_bytes).unwrap(), original);
+        trees.push(tree);
+    }
+    for path in ["word/document.xml", "word/settings.xml"] {
+        assert_eq!(blob(&trees[0], path), blob(&trees[1], path));
+    }
+    assert_ne!(blob(&trees[0], RSIDS_PART), blob(&trees[1], RSIDS_PART));
+
+    // Stripping is off unless the attribute is set.
+    fs::write(dir.path().join(".gitattributes"), "*.docx filter=docx\n").unwrap();
+    let pointer_bytes = clean(&repo, "doc.docx", &docx("00B2", &[])).unwrap();
+    assert!(Pointer::parse(std::str::from_utf8(&pointer_bytes).unwrap()).unwrap().rsids.is_none());
+}
+
+#[test]
+fn leaves_documents_with_own_side_channel_name_alone() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    fs::write(dir.path().join(".gitattributes"), format!("*.docx {STRIP_RSIDS_ATTRIBUTE}\n")).unwrap();
+    let original = docx("00B2", &[(RSIDS_PART, b"not ours")]);
+    let pointer_bytes = clean(&repo, "doc.docx", &original).unwrap();
+    assert!(Pointer::parse(std::str::from_utf8(&pointer_bytes).unwrap()).unwrap().rsids.is_none());
+    assert_eq!(smudge(&repo, &pointer_bytes).unwrap(), original);
+}
+
+#[test]
+fn strips_committed_documents_when_renormalized() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let original = docx("00B2", &[]);
+    let kept = clean(&repo, "doc.docx", &original).unwrap();
+    assert!(Pointer::parse(std::str::from_utf8(&kept).unwrap()).unwrap().rsids.is_none());
+    stage(&repo, "doc.docx", &kept);
+
+    fs::write(dir.path().join(".gitattributes"), format!("*.docx filter=docx {STRIP_RSIDS_ATTRIBUTE}\n")).unwrap();
+    let stripped = clean(&repo, "doc.docx", &original).unwrap();
+    assert!(Pointer::parse(std::str::from_utf8(&stripped).unwrap()).unwrap().rsids.is_some());
+    stage(&repo, "doc.docx", &stripped);
+    assert_eq!(clean(&repo, "doc.docx", &original).unwrap(), stripped);
+    assert_eq!(smudge(&repo, &stripped).unwrap(), original);
+}
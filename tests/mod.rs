@@ -1,2 +1,5 @@
 #![allow(clippy::pedantic)]
-#![allow(clippy::restriction)]
\ No newline at end of file
+#![allow(clippy::restriction)]
+mod filters;
+mod post_commit;
+mod utils;
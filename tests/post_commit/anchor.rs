@@ -0,0 +1,171 @@
+use docx_git_extension::post_commit::post_commit::{process_commit, source_commit, DELETE_POLICY_CONFIG};
+use git2::{Oid, Repository};
+use tempfile::tempdir;
+use super::{commit, pointer};
+
+fn anchored_tree(repo: &Repository, refname: &str) -> Oid {
+    repo.find_commit(repo.refname_to_id(refname).unwrap()).unwrap().tree_id()
+}
+
+#[test]
+fn anchors_every_document_of_a_commit_under_its_own_ref() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let (first, first_tree) = pointer(&repo, "a.docx", "first");
+    let (second, second_tree) = pointer(&repo, "b.docx", "second");
+
+    let oid = commit(&repo, &[], &[("a.docx", &first), ("b.docx", &second)]);
+    process_commit(&repo, &repo.find_commit(oid).unwrap(), None).unwrap();
+
+    assert_eq!(anchored_tree(&repo, "refs/docx/a.docx"), first_tree);
+    assert_eq!(anchored_tree(&repo, "refs/docx/b.docx"), second_tree);
+    let anchor = repo.find_commit(repo.refname_to_id("refs/docx/b.docx").unwrap()).unwrap();
+    assert_eq!(source_commit(&anchor), Some(oid));
+}
+
+/// Text of the document `rename` commits.
+const BODY: &str = "<w:body><w:p>First paragraph</w:p><w:p>Second paragraph</w:p><w:p>Third paragraph</w:p></w:body>";
+
+/// Commits `a.docx`, then renames it to `b.docx` with the pointer `renamed`, and returns the first anchor.
+fn rename(repo: &Repository, renamed: &str) -> Oid {
+    let (text, _tree) = pointer(repo, "a.docx", BODY);
+    let added = commit(repo, &[], &[("a.docx", &text)]);
+    process_commit(repo, &repo.find_commit(added).unwrap(), None).unwrap();
+    let anchored = repo.refname_to_id("refs/docx/a.docx").unwrap();
+    let moved = commit(repo, &[added], &[("b.docx", if renamed.is_empty() { &text } else { renamed })]);
+    process_commit(repo, &repo.find_commit(moved).unwrap(), None).unwrap();
+    anchored
+}
+
+#[test]
+fn moves_history_of_renamed_document_to_new_ref() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let anchored = rename(&repo, "");
+
+    assert_eq!(repo.refname_to_id("refs/docx/b.docx").unwrap(), anchored);
+    assert!(repo.find_reference("refs/docx/a.docx").is_err());
+    assert_eq!(repo.refname_to_id("refs/docx-deleted/a.docx").unwrap(), anchored);
+}
+
+#[test]
+fn keeps_history_of_renamed_and_edited_document() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    repo.config().unwrap().set_str(DELETE_POLICY_CONFIG, "delete").unwrap();
+    let (edited, edited_tree) = pointer(&repo, "b.docx", &BODY.replace("Third", "Last"));
+    let anchored = rename(&repo, &edited);
+
+    let renamed = repo.find_commit(repo.refname_to_id("refs/docx/b.docx").unwrap()).unwrap();
+    assert_eq!(renamed.tree_id(), edited_tree);
+    assert_eq!(renamed.parent_ids().collect::<Vec<_>>(), [anchored]);
+    assert!(repo.find_reference("refs/docx/a.docx").is_err());
+    assert!(repo.find_reference("refs/docx-deleted/a.docx").is_err());
+}
+
+#[test]
+fn keeps_old_ref_when_renamed_document_cannot_be_anchored() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let (_text, tree) = pointer(&repo, "a.docx", BODY);
+    let missing = format!("DOCX-POINTER-VERSION:3\nREF:refs/docx/b.docx\nHASH:{tree}\nTREE:{}\n", "1".repeat(40));
+    let anchored = rename(&repo, &missing);
+
+    assert!(repo.find_reference("refs/docx/b.docx").is_err());
+    assert_eq!(repo.refname_to_id("refs/docx/a.docx").unwrap(), anchored);
+}
+
+#[test]
+fn keeps_deleted_and_unrelated_added_documents_apart() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let unrelated = pointer(&repo, "b.docx", "<w:body><w:p>Something else entirely</w:p></w:body>").0;
+    let anchored = rename(&repo, &unrelated);
+
+    let added = repo.find_commit(repo.refname_to_id("refs/docx/b.docx").unwrap()).unwrap();
+    assert_eq!(added.parent_count(), 0);
+    assert_eq!(repo.refname_to_id("refs/docx-deleted/a.docx").unwrap(), anchored);
+}
+
+#[test]
+fn continues_history_of_renamed_document_on_older_branch() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let anchored = rename(&repo, "");
+    let base = source_commit(&repo.find_commit(anchored).unwrap()).unwrap();
+
+    // A branch cut before the rename still edits the document under its old path.
+    let (edited, _tree) = pointer(&repo, "a.docx", &BODY.replace("Third", "Last"));
+    let side = commit(&repo, &[base], &[("a.docx", &edited)]);
+    process_commit(&repo, &repo.find_commit(side).unwrap(), None).unwrap();
+
+    let continued = repo.find_commit(repo.refname_to_id("refs/docx/a.docx").unwrap()).unwrap();
+    assert_eq!(continued.parent_ids().collect::<Vec<_>>(), [anchored]);
+}
+
+#[test]
+fn merge_records_only_independent_parents() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let process = |oid: Oid| {
+        process_commit(&repo, &repo.find_commit(oid).unwrap(), None).unwrap();
+        repo.refname_to_id("refs/docx/a.docx").unwrap()
+    };
+    let (first, _tree) = pointer(&repo, "a.docx", "first");
+    let (second, _tree) = pointer(&repo, "a.docx", "second");
+    let (merged, merged_tree) = pointer(&repo, "a.docx", "merged");
+
+    let base = commit(&repo, &[], &[("a.docx", &first)]);
+    let first_anchor = process(base);
+    let side = commit(&repo, &[base], &[("a.docx", &first), ("notes.txt", "")]);
+    let main = commit(&repo, &[base], &[("a.docx", &second)]);
+    let second_anchor = process(main);
+    assert_eq!(repo.find_commit(second_anchor).unwrap().parent_ids().collect::<Vec<_>>(), [first_anchor]);
+
+    // The side branch still records the first version, which the second one already descends from.
+    let merge = commit(&repo, &[main, side], &[("a.docx", &merged), ("notes.txt", "")]);
+    let merge_anchor = repo.find_commit(process(merge)).unwrap();
+    assert_eq!(merge_anchor.tree_id(), merged_tree);
+    assert_eq!(merge_anchor.parent_ids().collect::<Vec<_>>(), [second_anchor]);
+}
+
+#[test]
+fn parents_versions_on_the_history_of_their_own_branch() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let process = |oid: Oid| process_commit(&repo, &repo.find_commit(oid).unwrap(), None).unwrap();
+    let anchor_of = |tree: Oid| {
+        let mut walk = repo.revwalk().unwrap();
+        walk.push_ref("refs/docx/a.docx").unwrap();
+        walk.map(|oid| repo.find_commit(oid.unwrap()).unwrap())
+            .find(|commit| commit.tree_id() == tree && source_commit(commit).is_some())
+            .unwrap()
+            .id()
+    };
+    let parents = |oid: Oid| repo.find_commit(oid).unwrap().parent_ids().collect::<Vec<_>>();
+    let (base_text, base_tree) = pointer(&repo, "a.docx", "base");
+    let (side_text, side_tree) = pointer(&repo, "a.docx", "side");
+    let (main_text, main_tree) = pointer(&repo, "a.docx", "main");
+    let (merged_text, merged_tree) = pointer(&repo, "a.docx", "merged");
+
+    let base = commit(&repo, &[], &[("a.docx", &base_text)]);
+    process(base);
+    let side = commit(&repo, &[base], &[("a.docx", &side_text)]);
+    process(side);
+    let main = commit(&repo, &[base], &[("a.docx", &main_text)]);
+    process(main);
+
+    // The main version descends from the base version only, and a join keeps the side version reachable.
+    assert_eq!(parents(anchor_of(main_tree)), [anchor_of(base_tree)]);
+    assert_eq!(parents(anchor_of(side_tree)), [anchor_of(base_tree)]);
+    let join = repo.refname_to_id("refs/docx/a.docx").unwrap();
+    assert_eq!(parents(join), [anchor_of(main_tree), anchor_of(side_tree)]);
+    assert_eq!(source_commit(&repo.find_commit(join).unwrap()), None);
+
+    // The merge joins both histories, so the ref moves past the join.
+    let merge = commit(&repo, &[main, side], &[("a.docx", &merged_text)]);
+    process(merge);
+    let merged = repo.refname_to_id("refs/docx/a.docx").unwrap();
+    assert_eq!(merged, anchor_of(merged_tree));
+    assert_eq!(parents(merged), [anchor_of(main_tree), anchor_of(side_tree)]);
+}
@@ -0,0 +1,53 @@
+use docx_git_extension::post_commit::post_commit::{process_commit, DELETE_POLICY_CONFIG};
+use git2::Repository;
+use tempfile::tempdir;
+use super::{commit, pointer};
+
+/// Commits a document, then deletes it under `policy`, and returns the repository and the anchor.
+fn delete_with(policy: &str) -> (tempfile::TempDir, Repository, git2::Oid) {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    repo.config().unwrap().set_str(DELETE_POLICY_CONFIG, policy).unwrap();
+    let (text, _tree) = pointer(&repo, "a.docx", "text");
+    let added = commit(&repo, &[], &[("a.docx", &text)]);
+    process_commit(&repo, &repo.find_commit(added).unwrap(), None).unwrap();
+    let anchored = repo.refname_to_id("refs/docx/a.docx").unwrap();
+
+    let deleted = commit(&repo, &[added], &[("notes.txt", "")]);
+    process_commit(&repo, &repo.find_commit(deleted).unwrap(), None).unwrap();
+    (dir, repo, anchored)
+}
+
+#[test]
+fn archives_ref_of_deleted_document() {
+    let (_dir, repo, anchored) = delete_with("archive");
+    assert!(repo.find_reference("refs/docx/a.docx").is_err());
+    assert_eq!(repo.refname_to_id("refs/docx-deleted/a.docx").unwrap(), anchored);
+    let reflog = repo.reflog("refs/docx-deleted/a.docx").unwrap();
+    assert_eq!(reflog.get(0).unwrap().message(), Some("Archived refs/docx/a.docx after a.docx was deleted"));
+}
+
+#[test]
+fn joins_archives_of_document_deleted_twice() {
+    let (_dir, repo, first) = delete_with("archive");
+    let (text, _tree) = pointer(&repo, "a.docx", "again");
+    let head = repo.head().unwrap().target().unwrap();
+    let readded = commit(&repo, &[head], &[("a.docx", &text)]);
+    process_commit(&repo, &repo.find_commit(readded).unwrap(), None).unwrap();
+    let second = repo.refname_to_id("refs/docx/a.docx").unwrap();
+    let deleted = commit(&repo, &[readded], &[("notes.txt", "")]);
+    process_commit(&repo, &repo.find_commit(deleted).unwrap(), None).unwrap();
+
+    let archive = repo.find_commit(repo.refname_to_id("refs/docx-deleted/a.docx").unwrap()).unwrap();
+    assert_eq!(archive.parent_ids().collect::<Vec<_>>(), [second, first]);
+}
+
+#[test]
+fn deletes_or_keeps_ref_of_deleted_document() {
+    let (_dir, repo, _anchored) = delete_with("delete");
+    assert!(repo.find_reference("refs/docx/a.docx").is_err());
+    assert!(repo.find_reference("refs/docx-deleted/a.docx").is_err());
+
+    let (_dir, repo, anchored) = delete_with("keep");
+    assert_eq!(repo.refname_to_id("refs/docx/a.docx").unwrap(), anchored);
+}
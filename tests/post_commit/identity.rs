@@ -0,0 +1,54 @@
+use docx_git_extension::post_commit::identity::{author_and_committer, sign, COMMITTER_NAME_CONFIG};
+use docx_git_extension::post_commit::post_commit::process_commit;
+use git2::{Repository, Signature};
+use tempfile::tempdir;
+use super::pointer;
+
+#[test]
+fn attributes_to_source_commit_with_overrides() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let mut config = repo.config().unwrap();
+    config.set_str("user.name", "Configured").unwrap();
+    config.set_str("user.email", "configured@example.com").unwrap();
+
+    let (author, _) = author_and_committer(&repo, None).unwrap();
+    assert_eq!((author.name(), author.email()), (Some("Configured"), Some("configured@example.com")));
+
+    let writer = Signature::now("Writer", "writer@example.com").unwrap();
+    let merger = Signature::now("Merger", "merger@example.com").unwrap();
+    let tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+    let source = repo.find_commit(repo.commit(None, &writer, &merger, "one", &tree, &[]).unwrap()).unwrap();
+
+    let (author, committer) = author_and_committer(&repo, Some(&source)).unwrap();
+    assert_eq!(author.name(), Some("Writer"));
+    assert_eq!(committer.email(), Some("merger@example.com"));
+
+    config.set_str(COMMITTER_NAME_CONFIG, "Docs Bot").unwrap();
+    let (author, committer) = author_and_committer(&repo, Some(&source)).unwrap();
+    assert_eq!((author.name(), author.email()), (Some("Docs Bot"), Some("writer@example.com")));
+    assert_eq!(committer.name(), Some("Docs Bot"));
+
+    assert_eq!(sign(&repo, "tree 0\n").unwrap(), None);
+}
+
+#[test]
+fn attributes_rewritten_commits_to_their_own_author() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let writer = Signature::now("Writer", "writer@example.com").unwrap();
+    let other = Signature::now("Other", "other@example.com").unwrap();
+    let (text, _tree) = pointer(&repo, "doc.docx", "text");
+    let mut root = repo.treebuilder(None).unwrap();
+    root.insert("doc.docx", repo.blob(text.as_bytes()).unwrap(), 0o100644).unwrap();
+    let tree = repo.find_tree(root.write().unwrap()).unwrap();
+    let rewritten = repo.commit(None, &writer, &writer, "rewritten", &tree, &[]).unwrap();
+    let empty = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+    repo.commit(Some("HEAD"), &other, &other, "head", &empty, &[]).unwrap();
+
+    // During post-rewrite HEAD is the tip of the rewritten branch, not the commit being processed.
+    process_commit(&repo, &repo.find_commit(rewritten).unwrap(), None).unwrap();
+    let anchor = repo.find_commit(repo.refname_to_id("refs/docx/doc.docx").unwrap()).unwrap();
+    assert_eq!(anchor.author().name(), Some("Writer"));
+    assert_eq!(anchor.committer().email(), Some("writer@example.com"));
+}
@@ -0,0 +1,33 @@
+mod anchor;
+mod delete;
+mod identity;
+mod pre_push;
+mod rewrite;
+
+use docx_git_extension::filters::refs::docx_refname;
+use git2::{Oid, Repository, Signature};
+
+/// Stores a one-part docx holding `text` and returns the pointer of `path` recording it, with its tree.
+/// The pointer uses the tree as its hash, so documents with different text have different hashes.
+pub fn pointer(repo: &Repository, path: &str, text: &str) -> (String, Oid) {
+    let mut docx = repo.treebuilder(None).unwrap();
+    docx.insert("document.xml", repo.blob(text.as_bytes()).unwrap(), 0o100644).unwrap();
+    let tree = docx.write().unwrap();
+    let refname = docx_refname(path).unwrap();
+    (format!("DOCX-POINTER-VERSION:3\nREF:{refname}\nHASH:{tree}\nTREE:{tree}\n"), tree)
+}
+
+/// Commits `files` on top of `parents` and moves `HEAD` to the commit.
+pub fn commit(repo: &Repository, parents: &[Oid], files: &[(&str, &str)]) -> Oid {
+    let signature = Signature::now("T", "t@x").unwrap();
+    let mut root = repo.treebuilder(None).unwrap();
+    for (path, contents) in files {
+        root.insert(*path, repo.blob(contents.as_bytes()).unwrap(), 0o100644).unwrap();
+    }
+    let tree = repo.find_tree(root.write().unwrap()).unwrap();
+    let parents: Vec<_> = parents.iter().map(|oid| repo.find_commit(*oid).unwrap()).collect();
+    let oid = repo.commit(None, &signature, &signature, "commit", &tree, &parents.iter().collect::<Vec<_>>()).unwrap();
+    repo.reference("refs/heads/main", oid, true, "").unwrap();
+    repo.set_head("refs/heads/main").unwrap();
+    oid
+}
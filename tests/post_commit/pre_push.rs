@@ -0,0 +1,144 @@
+use std::path::Path;
+use std::process::Command;
+use docx_git_extension::filters::refs::tracking_refspec;
+use docx_git_extension::post_commit::post_commit::{process_commit, source_commit};
+use docx_git_extension::post_commit::pre_push::{push_documents, push_refs, reachable_refs};
+use git2::{Oid, Repository, Signature};
+use tempfile::tempdir;
+use super::{commit, pointer};
+
+#[test]
+fn pushes_refs_of_pushed_documents() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path().join("local")).unwrap();
+    let signature = Signature::now("T", "t@x").unwrap();
+    let empty_tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+    let docx_commit = repo.commit(None, &signature, &signature, "docx", &empty_tree, &[]).unwrap();
+    for name in ["old.docx", "new.docx"] {
+        repo.reference(&format!("refs/docx/{name}"), docx_commit, false, "").unwrap();
+    }
+
+    let commit = |name: &str, parents: &[Oid]| {
+        let pointer = format!("DOCX-POINTER-VERSION:1\nREF:refs/docx/{name}\nHASH:00\n");
+        let mut root = repo.treebuilder(None).unwrap();
+        root.insert(name, repo.blob(pointer.as_bytes()).unwrap(), 0o100644).unwrap();
+        let tree = repo.find_tree(root.write().unwrap()).unwrap();
+        let parents: Vec<_> = parents.iter().map(|oid| repo.find_commit(*oid).unwrap()).collect();
+        repo.commit(None, &signature, &signature, name, &tree, &parents.iter().collect::<Vec<_>>()).unwrap()
+    };
+    let old = commit("old.docx", &[]);
+    let new = commit("new.docx", &[old]);
+    let missing = commit("missing.docx", &[new]);
+
+    let refs = reachable_refs(&repo, "origin", &[(missing, old)]).unwrap();
+    assert_eq!(refs.into_iter().collect::<Vec<_>>(), ["refs/docx/new.docx"]);
+    let refs = reachable_refs(&repo, "origin", &[(missing, Oid::zero())]).unwrap();
+    assert_eq!(refs.len(), 2);
+    assert!(reachable_refs(&repo, "origin", &[(Oid::zero(), missing)]).unwrap().is_empty());
+
+    let remote = Repository::init_bare(dir.path().join("remote.git")).unwrap();
+    push_refs(&repo, dir.path().join("remote.git").to_str().unwrap(), &refs).unwrap();
+    assert_eq!(remote.refname_to_id("refs/docx/old.docx").unwrap(), docx_commit);
+    assert_eq!(remote.refname_to_id("refs/docx/new.docx").unwrap(), docx_commit);
+}
+
+#[test]
+fn reads_only_pointers_changed_by_pushed_commits() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let signature = Signature::now("T", "t@x").unwrap();
+    let empty_tree = repo.find_tree(repo.treebuilder(None).unwrap().write().unwrap()).unwrap();
+    let docx_commit = repo.commit(None, &signature, &signature, "docx", &empty_tree, &[]).unwrap();
+    for name in ["a.docx", "b.docx"] {
+        repo.reference(&format!("refs/docx/{name}"), docx_commit, false, "").unwrap();
+    }
+
+    let commit = |files: &[(&str, &str)], parents: &[Oid]| {
+        let mut root = repo.treebuilder(None).unwrap();
+        for (name, contents) in files {
+            root.insert(name, repo.blob(contents.as_bytes()).unwrap(), 0o100644).unwrap();
+        }
+        let tree = repo.find_tree(root.write().unwrap()).unwrap();
+        let parents: Vec<_> = parents.iter().map(|oid| repo.find_commit(*oid).unwrap()).collect();
+        repo.commit(None, &signature, &signature, "commit", &tree, &parents.iter().collect::<Vec<_>>()).unwrap()
+    };
+    let pointer_a = "DOCX-POINTER-VERSION:1\nREF:refs/docx/a.docx\nHASH:00\n";
+    let pointer_b = "DOCX-POINTER-VERSION:1\nREF:refs/docx/b.docx\nHASH:00\n";
+    let base = commit(&[("a.docx", pointer_a)], &[]);
+    let readme = commit(&[("a.docx", pointer_a), ("README", "text")], &[base]);
+    let added = commit(&[("a.docx", pointer_a), ("b.docx", pointer_b), ("README", "text")], &[readme]);
+
+    assert!(reachable_refs(&repo, "origin", &[(readme, base)]).unwrap().is_empty());
+
+    let refs = reachable_refs(&repo, "origin", &[(added, base)]).unwrap();
+    assert_eq!(refs.into_iter().collect::<Vec<_>>(), ["refs/docx/b.docx"]);
+    let refs = reachable_refs(&repo, "origin", &[(added, Oid::zero())]).unwrap();
+    assert_eq!(refs.len(), 2);
+
+    // A new branch skips what the remote already has through its remote-tracking branches.
+    repo.remote("origin", "https://example.com/docs.git").unwrap();
+    repo.reference("refs/remotes/origin/main", readme, false, "").unwrap();
+    assert!(reachable_refs(&repo, "origin", &[(readme, Oid::zero())]).unwrap().is_empty());
+    repo.reference("refs/docx-deleted/b.docx", docx_commit, false, "").unwrap();
+    let refs = reachable_refs(&repo, "origin", &[(added, Oid::zero())]).unwrap();
+    assert_eq!(refs.into_iter().collect::<Vec<_>>(), ["refs/docx-deleted/b.docx", "refs/docx/b.docx"]);
+}
+
+/// Creates a clone of `hub` at `path` fetching docx references the way `install` configures.
+fn clone(hub: &Path, path: &Path) -> Repository {
+    let repo = Repository::init(path).unwrap();
+    repo.remote("origin", hub.to_str().unwrap()).unwrap();
+    repo.remote_add_fetch("origin", &tracking_refspec("origin")).unwrap();
+    fetch(&repo);
+    repo
+}
+
+/// Fetches the branches and docx references of `origin` with the configured refspecs.
+fn fetch(repo: &Repository) {
+    repo.find_remote("origin").unwrap().fetch(&[] as &[&str], None, None).unwrap();
+}
+
+/// Commits the pointer of `doc.docx` recording `text` on `parent`, anchors it and
+/// pushes it to `branch` of `origin` along with its docx reference.
+fn push(repo: &Repository, parent: &[Oid], text: &str, branch: &str) -> (Oid, Oid) {
+    let (text, tree) = pointer(repo, "doc.docx", text);
+    let oid = commit(repo, parent, &[("doc.docx", &text)]);
+    process_commit(repo, &repo.find_commit(oid).unwrap(), None).unwrap();
+    let line = format!("refs/heads/main {oid} refs/heads/{branch} {}\n", Oid::zero());
+    push_documents(repo, "origin", line.as_bytes()).unwrap();
+    let status = Command::new("git")
+        .arg("--git-dir")
+        .arg(repo.path())
+        .args(["push", "--quiet", "origin", &format!("{oid}:refs/heads/{branch}")])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    (oid, tree)
+}
+
+#[test]
+fn clones_extend_and_join_the_history_of_a_document() {
+    let dir = tempdir().unwrap();
+    let hub_path = dir.path().join("hub.git");
+    let hub = Repository::init_bare(&hub_path).unwrap();
+    let first = clone(&hub_path, &dir.path().join("first"));
+    let (base, _tree) = push(&first, &[], "base", "main");
+    let second = clone(&hub_path, &dir.path().join("second"));
+    assert!(second.find_reference("refs/remotes/origin/docx/doc.docx").is_ok());
+
+    // Both clones edit the document on their own branch and push.
+    push(&first, &[base], "first", "main");
+    let (edited, edited_tree) = push(&second, &[base], "second", "feature");
+
+    let tip = hub.find_commit(hub.refname_to_id("refs/docx/doc.docx").unwrap()).unwrap();
+    let anchors: Vec<Oid> = tip.parents().map(|parent| source_commit(&parent).unwrap()).collect();
+    assert_eq!(anchors.len(), 2, "the hub joins both histories");
+    assert!(anchors.contains(&edited));
+
+    // Fetching the diverged reference is forced into the remote-tracking namespace.
+    let first_tip = first.refname_to_id("refs/docx/doc.docx").unwrap();
+    fetch(&first);
+    assert_eq!(first.refname_to_id("refs/remotes/origin/docx/doc.docx").unwrap(), tip.id());
+    assert_eq!(first.refname_to_id("refs/docx/doc.docx").unwrap(), first_tip);
+    assert!(first.find_tree(edited_tree).is_ok());
+}
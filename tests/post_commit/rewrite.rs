@@ -0,0 +1,62 @@
+use docx_git_extension::post_commit::post_commit::{process_commit, process_rewrites, source_commit};
+use git2::{Oid, Repository, Signature};
+use tempfile::tempdir;
+
+#[test]
+fn amend_reanchors_document_ref() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let signature = Signature::now("T", "t@x").unwrap();
+
+    let mut docx = repo.treebuilder(None).unwrap();
+    docx.insert("document.xml", repo.blob(b"<w:document/>").unwrap(), 0o100644).unwrap();
+    let docx_tree = docx.write().unwrap();
+    let pointer = format!("DOCX-POINTER-VERSION:3\nREF:refs/docx/doc.docx\nHASH:00\nTREE:{docx_tree}\n");
+    let mut root = repo.treebuilder(None).unwrap();
+    root.insert("doc.docx", repo.blob(pointer.as_bytes()).unwrap(), 0o100644).unwrap();
+    let tree = repo.find_tree(root.write().unwrap()).unwrap();
+
+    let original = repo.commit(Some("HEAD"), &signature, &signature, "one", &tree, &[]).unwrap();
+    process_commit(&repo, &repo.find_commit(original).unwrap(), None).unwrap();
+    let anchored = repo.refname_to_id("refs/docx/doc.docx").unwrap();
+    assert_eq!(source_commit(&repo.find_commit(anchored).unwrap()), Some(original));
+
+    // The post-commit hook of the amend keeps the anchor, post-rewrite moves it to the new commit.
+    let amended = repo.find_commit(original).unwrap().amend(Some("HEAD"), None, None, None, Some("amended"), None).unwrap();
+    process_commit(&repo, &repo.find_commit(amended).unwrap(), None).unwrap();
+    assert_eq!(repo.refname_to_id("refs/docx/doc.docx").unwrap(), anchored);
+    process_commit(&repo, &repo.find_commit(amended).unwrap(), Some(original)).unwrap();
+
+    let reanchored = repo.find_commit(repo.refname_to_id("refs/docx/doc.docx").unwrap()).unwrap();
+    assert_eq!(source_commit(&reanchored), Some(amended));
+    assert_eq!(reanchored.tree_id(), docx_tree);
+    assert_eq!(reanchored.parent_ids().collect::<Vec<Oid>>(), [anchored]);
+}
+
+#[test]
+fn rewrite_reanchors_only_rewritten_documents() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    let signature = Signature::now("T", "t@x").unwrap();
+
+    let mut docx = repo.treebuilder(None).unwrap();
+    docx.insert("document.xml", repo.blob(b"<w:document/>").unwrap(), 0o100644).unwrap();
+    let docx_tree = docx.write().unwrap();
+    let pointer = |name: &str| format!("DOCX-POINTER-VERSION:3\nREF:refs/docx/{name}\nHASH:00\nTREE:{docx_tree}\n");
+    let mut root = repo.treebuilder(None).unwrap();
+    root.insert("a.docx", repo.blob(pointer("a.docx").as_bytes()).unwrap(), 0o100644).unwrap();
+    let first_tree = repo.find_tree(root.write().unwrap()).unwrap();
+    let first = repo.commit(Some("HEAD"), &signature, &signature, "a", &first_tree, &[]).unwrap();
+    root.insert("b.docx", repo.blob(pointer("b.docx").as_bytes()).unwrap(), 0o100644).unwrap();
+    let second_tree = repo.find_tree(root.write().unwrap()).unwrap();
+    let parent = repo.find_commit(first).unwrap();
+    let second = repo.commit(Some("HEAD"), &signature, &signature, "b", &second_tree, &[&parent]).unwrap();
+    let amended = repo.find_commit(second).unwrap().amend(Some("HEAD"), None, None, None, Some("amended"), None).unwrap();
+
+    process_rewrites(&repo, format!("{second} {amended}\n").as_bytes()).unwrap();
+
+    let anchored = repo.find_commit(repo.refname_to_id("refs/docx/b.docx").unwrap()).unwrap();
+    assert_eq!(source_commit(&anchored), Some(amended));
+    // a.docx was not changed by the rewritten commit, so its ref is left alone.
+    assert!(repo.find_reference("refs/docx/a.docx").is_err());
+}
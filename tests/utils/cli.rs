@@ -0,0 +1,49 @@
+use std::process::Command as Process;
+use docx_git_extension::utils::cli::{execute, parse, Command, EXIT_SUCCESS, EXIT_USAGE, VERSION};
+use log::LevelFilter;
+
+fn args(line: &str) -> Vec<String> {
+    line.split_whitespace().map(str::to_owned).collect()
+}
+
+#[test]
+fn parses_commands_and_global_options() {
+    let cli = parse(args("--verbose clean doc.docx")).unwrap();
+    assert_eq!(cli.level, Some(LevelFilter::Debug));
+    assert_eq!(cli.command, Command::Clean { path: "doc.docx".to_owned() });
+
+    assert_eq!(parse(args("install --global -q")).unwrap().level, Some(LevelFilter::Error));
+    assert_eq!(parse(args("install --global")).unwrap().command, Command::Install { global: true });
+    assert_eq!(parse(args("post-commit 0")).unwrap().command, Command::PostCommit);
+    assert_eq!(parse(args("pre-push origin https://example.com")).unwrap().command, Command::PrePush { remote: "origin".to_owned() });
+    assert_eq!(
+        parse(args("diff HEAD~1 -- a.docx -v")).unwrap().command,
+        Command::Diff { from: Some("HEAD~1".to_owned()), to: None, paths: args("a.docx -v") }
+    );
+    assert_eq!(parse(args("smudge --help")).unwrap().command, Command::Help);
+    assert_eq!(parse(args("-V")).unwrap().command, Command::Version);
+
+    assert!(parse(args("")).is_err());
+    assert!(parse(args("frobnicate")).is_err());
+    assert!(parse(args("smudge extra")).is_err());
+    assert!(parse(args("install --system")).is_err());
+    assert!(parse(args("diff a b c")).is_err());
+}
+
+#[test]
+fn runs_commands_of_every_binary() {
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    assert_eq!(execute(args("--version"), &mut out, &mut err), EXIT_SUCCESS);
+    assert_eq!(String::from_utf8(out).unwrap(), format!("git-docx {VERSION}\n"));
+
+    let (mut out, mut err) = (Vec::new(), Vec::new());
+    assert_eq!(execute(args("frobnicate"), &mut out, &mut err), EXIT_USAGE);
+    assert!(String::from_utf8(err).unwrap().contains("Unknown command `frobnicate`"));
+
+    // Binaries of earlier releases run their command through the same command line.
+    let output = Process::new(env!("CARGO_BIN_EXE_pre_push")).output().unwrap();
+    assert_eq!(output.status.code(), Some(EXIT_USAGE));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("pre-push requires the name of the remote"));
+    let output = Process::new(env!("CARGO_BIN_EXE_filters")).arg("--version").output().unwrap();
+    assert_eq!(String::from_utf8_lossy(&output.stdout), format!("git-docx {VERSION}\n"));
+}
@@ -0,0 +1,2 @@
+mod cli;
+mod setup;
\ No newline at end of file
@@ -0,0 +1,41 @@
+use std::fs;
+use std::path::Path;
+use docx_git_extension::utils::setup::{install, uninstall, Scope, ATTRIBUTES_LINE};
+use git2::Repository;
+use tempfile::tempdir;
+
+#[test]
+fn install_is_idempotent_and_reversible() {
+    let dir = tempdir().unwrap();
+    let repo = Repository::init(dir.path()).unwrap();
+    repo.remote("origin", "https://example.com/docs.git").unwrap();
+    repo.remote_add_fetch("origin", "refs/docx/*:refs/docx/*").unwrap();
+    fs::write(dir.path().join(".gitattributes"), "*.png binary").unwrap();
+    fs::write(repo.path().join("hooks").join("pre-push"), "#!/bin/sh\nexit 0\n").unwrap();
+    let bin_dir = Path::new("/opt/docx bin");
+
+    let changes = install(Scope::Local(&repo), bin_dir).unwrap();
+    assert!(changes.iter().any(|change| change.starts_with("Skipped existing hook")));
+    let config = repo.config().unwrap().snapshot().unwrap();
+    assert_eq!(config.get_str("filter.docx.clean").unwrap(), "\"/opt/docx bin/git-docx\" clean %f");
+    assert!(config.get_bool("filter.docx.required").unwrap());
+    let fetch: Vec<String> = repo.find_remote("origin").unwrap().fetch_refspecs().unwrap().iter().flatten().map(str::to_owned).collect();
+    assert!(fetch.contains(&"+refs/docx/*:refs/remotes/origin/docx/*".to_owned()));
+    assert!(!fetch.contains(&"refs/docx/*:refs/docx/*".to_owned()), "{fetch:?}");
+    assert_eq!(fetch.len(), 2);
+    let attributes = fs::read_to_string(dir.path().join(".gitattributes")).unwrap();
+    assert_eq!(attributes, format!("*.png binary\n{ATTRIBUTES_LINE}\n"));
+    let hook = fs::read_to_string(repo.path().join("hooks").join("post-rewrite")).unwrap();
+    assert!(hook.contains("exec \"/opt/docx bin/git-docx\" post-rewrite \"$@\""));
+
+    let changes = install(Scope::Local(&repo), bin_dir).unwrap();
+    assert_eq!(changes.len(), 1, "{changes:?}");
+
+    uninstall(Scope::Local(&repo)).unwrap();
+    assert!(uninstall(Scope::Local(&repo)).unwrap().is_empty());
+    assert!(repo.config().unwrap().get_entry("filter.docx.clean").is_err());
+    assert_eq!(fs::read_to_string(dir.path().join(".gitattributes")).unwrap(), "*.png binary\n");
+    assert!(!repo.path().join("hooks").join("post-commit").exists());
+    assert!(repo.path().join("hooks").join("pre-push").exists());
+    assert_eq!(repo.find_remote("origin").unwrap().fetch_refspecs().unwrap().len(), 1);
+}